@@ -0,0 +1,2 @@
+./compile_dynamic.sh shaders/pipeline/tests.rpsl && \
+cargo test golden_tests -- --ignored "$@"
@@ -0,0 +1,74 @@
+struct AnimationConstants {
+    uint source_offset;
+    uint dest_offset;
+    uint count;
+    // `INVALID` for vertices that aren't skinned.
+    uint joint_offset;
+    uint morph_deltas_offset;
+    uint num_morph_targets;
+    uint morph_weights_offset;
+    uint padding;
+};
+
+static const uint INVALID = 4294967295;
+
+[[vk::push_constant]]
+AnimationConstants constants;
+
+// The vertex buffers are tightly packed, so they're read as raw bytes.
+[[vk::binding(0)]] ByteAddressBuffer source_positions;
+[[vk::binding(1)]] ByteAddressBuffer source_normals;
+[[vk::binding(2)]] ByteAddressBuffer joint_indices;
+[[vk::binding(3)]] ByteAddressBuffer joint_weights;
+[[vk::binding(4)]] StructuredBuffer<float4x4> joint_matrices;
+// Pairs of (position delta, normal delta).
+[[vk::binding(5)]] ByteAddressBuffer morph_deltas;
+[[vk::binding(6)]] StructuredBuffer<float> morph_weights;
+[[vk::binding(7)]] RWByteAddressBuffer dest_positions;
+[[vk::binding(8)]] RWByteAddressBuffer dest_normals;
+[[vk::binding(9)]] ByteAddressBuffer source_tangents;
+[[vk::binding(10)]] RWByteAddressBuffer dest_tangents;
+
+[numthreads(64, 1, 1)]
+void animate_vertices(
+    uint3 id: SV_DispatchThreadID
+) {
+    if (id.x >= constants.count) {
+        return;
+    }
+
+    uint source = constants.source_offset + id.x;
+    uint dest = constants.dest_offset + id.x;
+
+    float3 position = asfloat(source_positions.Load3(source * 12));
+    float3 normal = asfloat(source_normals.Load3(source * 12));
+    float4 tangent = asfloat(source_tangents.Load4(source * 16));
+
+    // Morph targets are applied before skinning. Tangents aren't morphed.
+    for (uint target = 0; target < constants.num_morph_targets; target++) {
+        float weight = morph_weights[constants.morph_weights_offset + target];
+        uint delta = constants.morph_deltas_offset + target * constants.count + id.x;
+
+        position += weight * asfloat(morph_deltas.Load3(delta * 24));
+        normal += weight * asfloat(morph_deltas.Load3(delta * 24 + 12));
+    }
+
+    if (constants.joint_offset != INVALID) {
+        uint4 joints = joint_indices.Load4(source * 16) + constants.joint_offset;
+        float4 weights = asfloat(joint_weights.Load4(source * 16));
+
+        float4x4 skin_matrix =
+            weights.x * joint_matrices[joints.x] +
+            weights.y * joint_matrices[joints.y] +
+            weights.z * joint_matrices[joints.z] +
+            weights.w * joint_matrices[joints.w];
+
+        position = mul(skin_matrix, float4(position, 1.0)).xyz;
+        normal = mul(skin_matrix, float4(normal, 0.0)).xyz;
+        tangent.xyz = mul(skin_matrix, float4(tangent.xyz, 0.0)).xyz;
+    }
+
+    dest_positions.Store3(dest * 12, asuint(position));
+    dest_normals.Store3(dest * 12, asuint(normalize(normal)));
+    dest_tangents.Store4(dest * 16, asuint(float4(normalize(tangent.xyz), tangent.w)));
+}
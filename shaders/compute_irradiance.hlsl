@@ -0,0 +1,48 @@
+#include "ibl.hlsl"
+
+struct PushConstant {
+    // The source mip to sample from, so that the sparse samples don't alias.
+    float source_lod;
+};
+
+[[vk::push_constant]]
+PushConstant constant;
+
+[[vk::binding(0)]] TextureCube<float3> source;
+[[vk::binding(1)]] SamplerState samp;
+[[vk::binding(2), vk::image_format("rgba16f")]] RWTexture2DArray<float4> output;
+
+static const float SAMPLE_DELTA = 0.025;
+
+// Integrate the cosine-weighted radiance over the hemisphere around each texel's direction. The
+// result is divided by pi so that it only needs to be multiplied by the albedo.
+[numthreads(8, 8, 1)]
+void compute_irradiance(
+    uint3 id: SV_DispatchThreadID
+) {
+    uint width;
+    uint height;
+    uint layers;
+    output.GetDimensions(width, height, layers);
+
+    if (!(id.x < width && id.y < height)) {
+        return;
+    }
+
+    float3 normal = cube_direction(id, width);
+
+    float3 sum = 0.0;
+    float count = 0.0;
+
+    for (float phi = 0.0; phi < 2.0 * PI; phi += SAMPLE_DELTA) {
+        for (float theta = 0.0; theta < 0.5 * PI; theta += SAMPLE_DELTA) {
+            float3 tangent_space = float3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            float3 direction = tangent_to_world(tangent_space, normal);
+
+            sum += source.SampleLevel(samp, direction, constant.source_lod) * cos(theta) * sin(theta);
+            count += 1.0;
+        }
+    }
+
+    output[id] = float4(PI * sum / count, 1.0);
+}
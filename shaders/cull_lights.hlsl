@@ -0,0 +1,119 @@
+#include "lights.hlsl"
+
+[[vk::binding(0)]] Texture2D<float> depth_tex;
+[[vk::binding(1)]] ConstantBuffer<ClusterUniforms> uniforms;
+[[vk::binding(2)]] StructuredBuffer<Light> lights;
+[[vk::binding(3)]] RWStructuredBuffer<uint> cluster_light_counts;
+[[vk::binding(4)]] RWStructuredBuffer<uint> cluster_light_indices;
+
+static const uint GROUP_SIZE = 8;
+static const float FLOAT_MAX = 3.402823466e+38;
+
+// Positive floats sort the same way as their bits, so these can be updated atomically.
+groupshared uint min_depth_bits;
+groupshared uint max_depth_bits;
+groupshared uint light_count;
+
+// Whether a light's sphere of influence touches a view space box. Lights without a range can't be
+// culled.
+bool light_affects_cluster(Light light, float3 aabb_min, float3 aabb_max) {
+    if (light.ty == LIGHT_DIRECTIONAL || light.range <= 0.0) {
+        return true;
+    }
+
+    float3 center = mul(uniforms.view, float4(light.position, 1.0)).xyz;
+    float3 offset = center - clamp(center, aabb_min, aabb_max);
+    return dot(offset, offset) <= light.range * light.range;
+}
+
+// Each workgroup handles a screen tile. The depth range of the tile is found from the depth
+// buffer, then every depth slice of the tile is shrunk to fit within that range and tested against
+// the lights.
+[numthreads(GROUP_SIZE, GROUP_SIZE, 1)]
+void cull_lights(
+    uint3 tile: SV_GroupID,
+    uint3 thread_id: SV_GroupThreadID,
+    uint thread_index: SV_GroupIndex
+) {
+    if (thread_index == 0) {
+        min_depth_bits = asuint(FLOAT_MAX);
+        max_depth_bits = 0;
+    }
+
+    GroupMemoryBarrierWithGroupSync();
+
+    uint width;
+    uint height;
+    depth_tex.GetDimensions(width, height);
+
+    uint2 tile_start = uint2(float2(tile.xy) * uniforms.tile_size);
+    uint2 tile_end = min(uint2(ceil(float2(tile.xy + 1) * uniforms.tile_size)), uint2(width, height));
+
+    for (uint y = tile_start.y + thread_id.y; y < tile_end.y; y += GROUP_SIZE) {
+        for (uint x = tile_start.x + thread_id.x; x < tile_end.x; x += GROUP_SIZE) {
+            float depth = depth_tex.Load(int3(x, y, 0));
+
+            // The depth buffer is reversed, so 0 is infinitely far away and nothing was drawn.
+            if (depth > 0.0) {
+                uint view_depth_bits = asuint(uniforms.projection_near / depth);
+                InterlockedMin(min_depth_bits, view_depth_bits);
+                InterlockedMax(max_depth_bits, view_depth_bits);
+            }
+        }
+    }
+
+    GroupMemoryBarrierWithGroupSync();
+
+    float min_depth = asfloat(min_depth_bits);
+    float max_depth = asfloat(max_depth_bits);
+
+    // The corners of the tile in normalized device coordinates, with Y pointing up.
+    float2 screen_size = float2(width, height);
+    float2 ndc_min = float2(tile_start.x, tile_end.y) / screen_size * float2(2.0, -2.0) + float2(-1.0, 1.0);
+    float2 ndc_max = float2(tile_end.x, tile_start.y) / screen_size * float2(2.0, -2.0) + float2(-1.0, 1.0);
+
+    for (uint slice = 0; slice < uniforms.dimensions.z; slice++) {
+        if (thread_index == 0) {
+            light_count = 0;
+        }
+
+        GroupMemoryBarrierWithGroupSync();
+
+        float near_depth = max(slice_depth(slice, uniforms), min_depth);
+        float far_depth = min(slice_depth(slice + 1, uniforms), max_depth);
+
+        // Empty tiles and slices outside the tile's depth range have no pixels to light.
+        if (near_depth <= far_depth) {
+            // The view space box around the part of the frustum that the cluster covers. The
+            // camera looks down -Z.
+            float2 near_min = ndc_min * near_depth / uniforms.projection_scale;
+            float2 near_max = ndc_max * near_depth / uniforms.projection_scale;
+            float2 far_min = ndc_min * far_depth / uniforms.projection_scale;
+            float2 far_max = ndc_max * far_depth / uniforms.projection_scale;
+
+            float3 aabb_min = float3(min(near_min, far_min), -far_depth);
+            float3 aabb_max = float3(max(near_max, far_max), -near_depth);
+
+            for (uint i = thread_index; i < uniforms.light_count; i += GROUP_SIZE * GROUP_SIZE) {
+                if (light_affects_cluster(lights[i], aabb_min, aabb_max)) {
+                    uint slot;
+                    InterlockedAdd(light_count, 1, slot);
+
+                    if (slot < MAX_LIGHTS_PER_CLUSTER) {
+                        uint cluster = cluster_index(uint3(tile.xy, slice), uniforms);
+                        cluster_light_indices[cluster * MAX_LIGHTS_PER_CLUSTER + slot] = i;
+                    }
+                }
+            }
+        }
+
+        GroupMemoryBarrierWithGroupSync();
+
+        if (thread_index == 0) {
+            cluster_light_counts[cluster_index(uint3(tile.xy, slice), uniforms)] = min(light_count, MAX_LIGHTS_PER_CLUSTER);
+        }
+
+        // Wait for the count to be read before it's reset for the next slice.
+        GroupMemoryBarrierWithGroupSync();
+    }
+}
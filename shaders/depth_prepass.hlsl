@@ -0,0 +1,24 @@
+#include "instance_transform.hlsl"
+
+struct PushConstant {
+    float4x4 combined_matrix;
+};
+
+[[vk::push_constant]]
+PushConstant constant;
+
+[shader("vertex")]
+float4 VSMain(
+    float3 position: POSITION,
+    float4 instance_column_0: TEXCOORD0,
+    float4 instance_column_1: TEXCOORD1,
+    float4 instance_column_2: TEXCOORD2,
+    float4 instance_column_3: TEXCOORD3
+): SV_Position {
+    float4x4 transform = instance_transform(instance_column_0, instance_column_1, instance_column_2, instance_column_3);
+    float3 world_position;
+    return transform_position(position, transform, constant.combined_matrix, world_position);
+}
+
+[shader("pixel")]
+void PSMain() {}
@@ -0,0 +1,46 @@
+// Helpers for precomputing image-based lighting from a cubemap.
+
+#include "pbr.hlsl"
+
+// The direction through the center of a texel of a cubemap face, following the D3D and Vulkan
+// face order of +X, -X, +Y, -Y, +Z, -Z.
+float3 cube_direction(uint3 id, uint size) {
+    float2 uv = (float2(id.xy) + 0.5) / float(size) * 2.0 - 1.0;
+
+    float3 directions[6] = {
+        float3(1.0, -uv.y, -uv.x),
+        float3(-1.0, -uv.y, uv.x),
+        float3(uv.x, 1.0, uv.y),
+        float3(uv.x, -1.0, -uv.y),
+        float3(uv.x, -uv.y, 1.0),
+        float3(-uv.x, -uv.y, -1.0)
+    };
+
+    return normalize(directions[id.z]);
+}
+
+// A low-discrepancy point set, for evenly spread samples.
+float2 hammersley(uint i, uint count) {
+    return float2(float(i) / float(count), float(reversebits(i)) * 2.3283064365386963e-10);
+}
+
+// Build a basis around `normal` to move tangent-space vectors into.
+float3 tangent_to_world(float3 vec, float3 normal) {
+    float3 up = abs(normal.z) < 0.999 ? float3(0.0, 0.0, 1.0) : float3(1.0, 0.0, 0.0);
+    float3 tangent = normalize(cross(up, normal));
+    float3 bitangent = cross(normal, tangent);
+    return tangent * vec.x + bitangent * vec.y + normal * vec.z;
+}
+
+// A half vector distributed according to the GGX normal distribution function.
+float3 importance_sample_ggx(float2 xi, float3 normal, float alpha) {
+    float phi = 2.0 * PI * xi.x;
+    float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y));
+    float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return tangent_to_world(float3(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta), normal);
+}
+
+// Matches the clamp in `brdf`.
+float roughness_to_alpha(float perceptual_roughness) {
+    return max(perceptual_roughness * perceptual_roughness, 0.002);
+}
@@ -0,0 +1,16 @@
+// Transforming vertices by the matrices in the instance buffer.
+
+// The columns become rows here, so vectors are multiplied on the left.
+float4x4 instance_transform(float4 column_0, float4 column_1, float4 column_2, float4 column_3) {
+    return float4x4(column_0, column_1, column_2, column_3);
+}
+
+// Shared by `moon.hlsl` and `depth_prepass.hlsl`, as `draw` only passes where its depths are equal
+// to those of the depth prepass. `precise` stops each shader's compilation from fusing the
+// multiplies and adds differently.
+float4 transform_position(float3 position, float4x4 transform, float4x4 combined_matrix, out float3 world_position) {
+    precise float3 world = mul(float4(position, 1.0), transform).xyz;
+    precise float4 clip_position = mul(combined_matrix, float4(world, 1.0));
+    world_position = world;
+    return clip_position;
+}
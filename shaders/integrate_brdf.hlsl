@@ -0,0 +1,47 @@
+#include "shared.hlsl"
+#include "ibl.hlsl"
+
+[[vk::binding(0), vk::image_format("rg16f")]] RWTexture2D<float2> output;
+
+static const uint SAMPLE_COUNT = 1024;
+
+// Integrate the specular BRDF for a white F0 into a scale and a bias for F0, giving the second
+// half of the split-sum approximation. X is n dot v and Y is the perceptual roughness.
+[numthreads(8, 8, 1)]
+void integrate_brdf(
+    uint3 id: SV_DispatchThreadID
+) {
+    uint2 size = texture_size(output);
+
+    if (!(id.x < size.x && id.y < size.y)) {
+        return;
+    }
+
+    float n_dot_v = (float(id.x) + 0.5) / float(size.x);
+    float alpha = roughness_to_alpha((float(id.y) + 0.5) / float(size.y));
+
+    float3 normal = float3(0.0, 0.0, 1.0);
+    float3 view = float3(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+
+    float2 sum = 0.0;
+
+    for (uint i = 0; i < SAMPLE_COUNT; i++) {
+        float3 half_vector = importance_sample_ggx(hammersley(i, SAMPLE_COUNT), normal, alpha);
+        float3 light = reflect(-view, half_vector);
+
+        float n_dot_l = saturate(light.z);
+        float n_dot_h = saturate(half_vector.z);
+        float v_dot_h = saturate(dot(view, half_vector));
+
+        if (n_dot_l > 0.0) {
+            // The BRDF times n dot l, divided by the pdf of the sample. The distribution term
+            // cancels out.
+            float visibility = v_smith_ggx_correlated(n_dot_v, n_dot_l, alpha) * 4.0 * n_dot_l * v_dot_h / n_dot_h;
+            float fresnel = pow(1.0 - v_dot_h, 5.0);
+
+            sum += float2(1.0 - fresnel, fresnel) * visibility;
+        }
+    }
+
+    output[id.xy] = sum / float(SAMPLE_COUNT);
+}
@@ -0,0 +1,90 @@
+// Punctual lights and the cluster grid that `cull_lights` sorts them into.
+
+static const uint LIGHT_DIRECTIONAL = 0;
+static const uint LIGHT_POINT = 1;
+static const uint LIGHT_SPOT = 2;
+
+// Matches `Light` in `src/lights.rs`.
+struct Light {
+    float3 position;
+    float range;
+    float3 direction;
+    uint ty;
+    float3 color;
+    float intensity;
+    float inner_cone_cos;
+    float outer_cone_cos;
+};
+
+// Matches `MAX_LIGHTS_PER_CLUSTER` in `src/lights.rs`.
+static const uint MAX_LIGHTS_PER_CLUSTER = 128;
+
+// Matches `ClusterUniforms` in `src/lights.rs`.
+struct ClusterUniforms {
+    float4x4 view;
+    // The X and Y scales of the projection matrix, and its near plane.
+    float2 projection_scale;
+    float projection_near;
+    uint light_count;
+    uint3 dimensions;
+    // Depth slices are spaced exponentially between these distances, with the first and last
+    // slices extending to the camera and to infinity.
+    float z_near;
+    // The size of each cluster on screen, in pixels.
+    float2 tile_size;
+    float z_far;
+};
+
+// The view space distance at which a depth slice starts.
+float slice_depth(uint slice, ClusterUniforms uniforms) {
+    if (slice == 0) {
+        return 0.0;
+    }
+
+    if (slice >= uniforms.dimensions.z) {
+        return 3.402823466e+38;
+    }
+
+    return uniforms.z_near * pow(uniforms.z_far / uniforms.z_near, float(slice) / float(uniforms.dimensions.z));
+}
+
+uint depth_slice(float view_depth, ClusterUniforms uniforms) {
+    float slice = log(view_depth / uniforms.z_near) / log(uniforms.z_far / uniforms.z_near) * float(uniforms.dimensions.z);
+    return uint(clamp(slice, 0.0, float(uniforms.dimensions.z - 1)));
+}
+
+uint cluster_index(uint3 cluster, ClusterUniforms uniforms) {
+    return (cluster.z * uniforms.dimensions.y + cluster.y) * uniforms.dimensions.x + cluster.x;
+}
+
+// The direction towards a light and the illuminance it gives to a surface facing it, using the
+// falloff recommended by `KHR_lights_punctual`.
+void evaluate_light(Light light, float3 position, out float3 direction, out float3 illuminance) {
+    illuminance = light.color * light.intensity;
+
+    if (light.ty == LIGHT_DIRECTIONAL) {
+        direction = -light.direction;
+        return;
+    }
+
+    float3 to_light = light.position - position;
+    float distance_squared = max(dot(to_light, to_light), 1e-4);
+    direction = to_light * rsqrt(distance_squared);
+
+    // Inverse square falloff, smoothly windowed to reach zero at the light's range.
+    float attenuation = 1.0 / distance_squared;
+
+    if (light.range > 0.0) {
+        float ratio = distance_squared / (light.range * light.range);
+        float window = saturate(1.0 - ratio * ratio);
+        attenuation *= window * window;
+    }
+
+    if (light.ty == LIGHT_SPOT) {
+        float cos_angle = dot(light.direction, -direction);
+        float spot = saturate((cos_angle - light.outer_cone_cos) / max(light.inner_cone_cos - light.outer_cone_cos, 1e-4));
+        attenuation *= spot * spot;
+    }
+
+    illuminance *= attenuation;
+}
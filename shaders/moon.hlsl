@@ -1,8 +1,12 @@
-#include "quaternion.hlsl"
+#include "pbr.hlsl"
+#include "lights.hlsl"
+#include "shadows.hlsl"
+#include "instance_transform.hlsl"
 
 struct PushConstant {
     float4x4 combined_matrix;
     float3 camera_pos;
+    float environment_boost;
 };
 
 static const uint INVALID = 4294967295;
@@ -15,6 +19,14 @@ struct MaterialInfo {
     uint albedo_texture;
     uint normal_texture;
     uint emissive_texture;
+    uint albedo_uv_set;
+    uint normal_uv_set;
+    uint emissive_uv_set;
+    uint metallic_roughness_texture;
+    uint occlusion_texture;
+    uint metallic_roughness_uv_set;
+    uint occlusion_uv_set;
+    float occlusion_strength;
 };
 
 [[vk::push_constant]]
@@ -26,25 +38,48 @@ struct Varying {
     float2 uv: TEXCOORD0;
     float3 normal: NORMAL0;
     uint material_id: TEXCOORD1;
+    float2 second_uv: TEXCOORD2;
+    float4 tangent: TANGENT0;
+    float4 color: COLOR0;
+    // The distance from the camera along its forward axis.
+    float view_depth: TEXCOORD3;
 };
 
+// Transforms normals so that they stay perpendicular to surfaces under non-uniform scale. This is
+// the cofactor matrix of the upper 3x3, which is the inverse transpose scaled by the determinant.
+float3x3 normal_matrix(float4x4 transform) {
+    float3 row_0 = transform[0].xyz;
+    float3 row_1 = transform[1].xyz;
+    float3 row_2 = transform[2].xyz;
+    float3x3 cofactor = float3x3(cross(row_1, row_2), cross(row_2, row_0), cross(row_0, row_1));
+    // Mirrored transforms have a negative determinant, which would flip the normals.
+    return dot(row_0, cross(row_1, row_2)) < 0.0 ? -cofactor : cofactor;
+}
+
 [shader("vertex")]
 Varying VSMain(
     float3 position: POSITION,
     float2 uv: TEXCOORD0,
     float3 normal: NORMAL0,
     uint material_id: TEXCOORD1,
-    float3 instance_position: TEXCOORD2,
-    float instance_scale: TEXCOORD3,
-    float4 instance_rotation: TEXCOORD4
+    float4 instance_column_0: TEXCOORD2,
+    float4 instance_column_1: TEXCOORD3,
+    float4 instance_column_2: TEXCOORD4,
+    float4 instance_column_3: TEXCOORD5,
+    float2 second_uv: TEXCOORD6,
+    float4 tangent: TANGENT0,
+    float4 color: COLOR0
 ) {
-    Similarity transform = Similarity::from(instance_position, Quaternion::from_float4(instance_rotation), instance_scale);
+    float4x4 transform = instance_transform(instance_column_0, instance_column_1, instance_column_2, instance_column_3);
     Varying output;
-    output.position = transform * position;
-    output.builtin_position = mul(constant.combined_matrix, float4(output.position, 1.0));
+    output.builtin_position = transform_position(position, transform, constant.combined_matrix, output.position);
     output.uv = uv;
     output.material_id = material_id;
-    output.normal = transform.rotation * normal;
+    output.normal = mul(normal, normal_matrix(transform));
+    output.second_uv = second_uv;
+    output.tangent = float4(mul(float4(tangent.xyz, 0.0), transform).xyz, tangent.w);
+    output.color = color;
+    output.view_depth = output.builtin_position.w;
     return output;
 }
 
@@ -52,31 +87,51 @@ Varying VSMain(
 [[vk::binding(0)]] Texture2D<float3> tex[];
 [[vk::binding(1)]] SamplerState samp;
 [[vk::binding(2)]] StructuredBuffer<MaterialInfo> infos;
-
-float length_squared(float3 vec) {
-    return dot(vec, vec);
-}
-
-float3x3 compute_cotangent_frame(
+// Image-based lighting precomputed from the skybox.
+[[vk::binding(3)]] TextureCube<float3> specular_environment;
+[[vk::binding(4)]] TextureCube<float3> irradiance;
+[[vk::binding(5)]] Texture2D<float2> brdf_lut;
+[[vk::binding(6)]] SamplerState environment_sampler;
+[[vk::binding(7)]] StructuredBuffer<Light> lights;
+[[vk::binding(8)]] Texture2DArray<float> shadow_map;
+[[vk::binding(9)]] SamplerComparisonState shadow_sampler;
+[[vk::binding(10)]] ConstantBuffer<ShadowUniforms> shadow_uniforms;
+// The lights affecting each cluster, from `cull_lights`.
+[[vk::binding(0, 1)]] ConstantBuffer<ClusterUniforms> cluster_uniforms;
+[[vk::binding(1, 1)]] StructuredBuffer<uint> cluster_light_counts;
+[[vk::binding(2, 1)]] StructuredBuffer<uint> cluster_light_indices;
+
+
+// The split-sum approximation of the light reflected from the environment. The diffuse part uses
+// the irradiance, and the specular part combines the prefiltered radiance with the scale and bias
+// to F0 from the BRDF lookup table.
+float3 environment_lighting(
     float3 normal,
-    float3 position,
-    float2 uv
+    float3 view,
+    float3 base_color,
+    float metallic,
+    float perceptual_roughness
 ) {
-    float3 delta_pos_x = ddx(position);
-    float3 delta_pos_y = ddy(position);
-    float2 delta_uv_x = ddx(uv);
-    float2 delta_uv_y = ddy(uv);
+    float n_dot_v = max(dot(normal, view), 1e-4);
+    float3 reflection = reflect(-view, normal);
+
+    uint width;
+    uint height;
+    uint mip_count;
+    specular_environment.GetDimensions(0, width, height, mip_count);
 
-    float3 delta_pos_y_perp = cross(delta_pos_y, normal);
-    float3 delta_pos_x_perp = cross(normal, delta_pos_x);
+    float3 radiance = specular_environment.SampleLevel(environment_sampler, reflection, perceptual_roughness * float(mip_count - 1));
+    float2 scale_bias = brdf_lut.SampleLevel(environment_sampler, float2(n_dot_v, perceptual_roughness), 0.0);
 
-    float3 t = delta_pos_y_perp * delta_uv_x.x + delta_pos_x_perp * delta_uv_y.x;
-    float3 b = delta_pos_y_perp * delta_uv_x.y + delta_pos_x_perp * delta_uv_y.y;
+    float3 specular = radiance * (specular_color(base_color, metallic) * scale_bias.x + scale_bias.y);
+    float3 diffuse = irradiance.SampleLevel(environment_sampler, normal, 0.0) * base_color * (1.0 - metallic);
 
-    float invmax = 1.0 / sqrt(max(length_squared(t), length_squared(b)));
-    return transpose(float3x3(t * invmax, b * invmax, normal));
+    return diffuse + specular;
+}
+
+float2 select_uv(Varying varying, uint uv_set) {
+    return uv_set == 1 ? varying.second_uv : varying.uv;
 }
-    
 
 [shader("pixel")]
 float4 PSMain(
@@ -86,27 +141,65 @@ float4 PSMain(
 
     float3 normal = normalize(varying.normal);
 
-    float3 view_vector = constant.camera_pos - varying.position;
-
     if (info.normal_texture != INVALID) {
-        float3 tex_normal = tex[info.normal_texture].Sample(samp, varying.uv).xyz * 255.0 / 127.0 - 128.0 / 127.0;
-        normal = normalize(mul(compute_cotangent_frame(normal, -view_vector, varying.uv), tex_normal));
+        float3 tex_normal = tex[info.normal_texture].Sample(samp, select_uv(varying, info.normal_uv_set)).xyz * 255.0 / 127.0 - 128.0 / 127.0;
+        float3 tangent = normalize(varying.tangent.xyz);
+        float3 bitangent = cross(normal, tangent) * varying.tangent.w;
+        normal = normalize(tex_normal.x * tangent + tex_normal.y * bitangent + tex_normal.z * normal);
     }
 
-    float3 sun_dir = normalize(float3(1,1,1));
-    float brightness = max(dot(normal, sun_dir), 0.0);
-
-    float3 albedo = info.base_color_factor.rgb;
+    float3 albedo = info.base_color_factor.rgb * varying.color.rgb;
 
     if (info.albedo_texture != INVALID) {
-        albedo *= tex[info.albedo_texture].Sample(samp, varying.uv).xyz;
+        albedo *= tex[info.albedo_texture].Sample(samp, select_uv(varying, info.albedo_uv_set)).xyz;
+    }
+
+    float metallic = info.metallic_factor;
+    float roughness = info.roughness_factor;
+
+    // Roughness is in the green channel and metalness is in the blue channel.
+    if (info.metallic_roughness_texture != INVALID) {
+        float3 metallic_roughness = tex[info.metallic_roughness_texture].Sample(samp, select_uv(varying, info.metallic_roughness_uv_set));
+        roughness *= metallic_roughness.g;
+        metallic *= metallic_roughness.b;
+    }
+
+    float occlusion = 1.0;
+
+    if (info.occlusion_texture != INVALID) {
+        float sampled_occlusion = tex[info.occlusion_texture].Sample(samp, select_uv(varying, info.occlusion_uv_set)).r;
+        occlusion = lerp(1.0, sampled_occlusion, info.occlusion_strength);
     }
 
     float3 emissive = info.emissive_factor;
 
     if (info.emissive_texture != INVALID) {
-        emissive *= tex[info.emissive_texture].Sample(samp, varying.uv).xyz;
+        emissive *= tex[info.emissive_texture].Sample(samp, select_uv(varying, info.emissive_uv_set)).xyz;
+    }
+
+    float3 view = normalize(constant.camera_pos - varying.position);
+
+    float3 color = 0.0;
+
+    uint2 tile = uint2(varying.builtin_position.xy / cluster_uniforms.tile_size);
+    uint3 cluster_coords = uint3(min(tile, cluster_uniforms.dimensions.xy - 1), depth_slice(varying.view_depth, cluster_uniforms));
+    uint cluster = cluster_index(cluster_coords, cluster_uniforms);
+
+    for (uint i = 0; i < cluster_light_counts[cluster]; i++) {
+        uint light_index = cluster_light_indices[cluster * MAX_LIGHTS_PER_CLUSTER + i];
+        float3 direction;
+        float3 illuminance;
+        evaluate_light(lights[light_index], varying.position, direction, illuminance);
+
+        if (light_index == shadow_uniforms.light_index) {
+            illuminance *= shadow_factor(shadow_map, shadow_sampler, shadow_uniforms, varying.position, normalize(varying.normal), varying.view_depth);
+        }
+
+        color += brdf(normal, view, direction, albedo, metallic, roughness) * illuminance;
     }
 
-    return float4(albedo * brightness + emissive, 1.0);
+    // Occlusion only applies to indirect light.
+    color += environment_lighting(normal, view, albedo, metallic, roughness) * constant.environment_boost * occlusion;
+
+    return float4(color + emissive, 1.0);
 }
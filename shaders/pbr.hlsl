@@ -0,0 +1,56 @@
+// The glTF metallic-roughness BRDF: Lambertian diffuse plus a Cook-Torrance GGX specular lobe.
+
+static const float PI = 3.14159265358979323846;
+
+// The GGX/Trowbridge-Reitz normal distribution function.
+float d_ggx(float n_dot_h, float alpha) {
+    float alpha_squared = alpha * alpha;
+    float f = (n_dot_h * alpha_squared - n_dot_h) * n_dot_h + 1.0;
+    return alpha_squared / (PI * f * f);
+}
+
+// The height-correlated Smith masking-shadowing term, divided by `4 * n_dot_l * n_dot_v`.
+float v_smith_ggx_correlated(float n_dot_v, float n_dot_l, float alpha) {
+    float alpha_squared = alpha * alpha;
+    float ggx_v = n_dot_l * sqrt((n_dot_v - n_dot_v * alpha_squared) * n_dot_v + alpha_squared);
+    float ggx_l = n_dot_v * sqrt((n_dot_l - n_dot_l * alpha_squared) * n_dot_l + alpha_squared);
+    return 0.5 / (ggx_v + ggx_l);
+}
+
+float3 f_schlick(float3 f0, float v_dot_h) {
+    return f0 + (1.0 - f0) * pow(1.0 - v_dot_h, 5.0);
+}
+
+// Dielectrics reflect 4% of light head-on, metals reflect their base color.
+float3 specular_color(float3 base_color, float metallic) {
+    return lerp(float3(0.04, 0.04, 0.04), base_color, metallic);
+}
+
+// The light reflected towards `view` for each unit of radiance arriving from `light`, including
+// the cosine term. All the vectors point away from the surface.
+float3 brdf(
+    float3 normal,
+    float3 view,
+    float3 light,
+    float3 base_color,
+    float metallic,
+    float perceptual_roughness
+) {
+    float3 half_vector = normalize(view + light);
+
+    // Clamped to avoid dividing by zero at grazing angles.
+    float n_dot_v = max(dot(normal, view), 1e-4);
+    float n_dot_l = saturate(dot(normal, light));
+    float n_dot_h = saturate(dot(normal, half_vector));
+    float v_dot_h = saturate(dot(view, half_vector));
+
+    // Perceptual roughness is squared, and kept above zero so that highlights don't vanish.
+    float alpha = max(perceptual_roughness * perceptual_roughness, 0.002);
+
+    float3 fresnel = f_schlick(specular_color(base_color, metallic), v_dot_h);
+
+    float3 specular = d_ggx(n_dot_h, alpha) * v_smith_ggx_correlated(n_dot_v, n_dot_l, alpha) * fresnel;
+    float3 diffuse = (1.0 - fresnel) * (1.0 - metallic) * base_color / PI;
+
+    return (diffuse + specular) * n_dot_l;
+}
@@ -0,0 +1,69 @@
+#include "ibl.hlsl"
+
+struct PushConstant {
+    float roughness;
+    // The width of the first mip of the source cubemap and its number of mips.
+    float source_size;
+    float source_mips;
+};
+
+[[vk::push_constant]]
+PushConstant constant;
+
+[[vk::binding(0)]] TextureCube<float3> source;
+[[vk::binding(1)]] SamplerState samp;
+[[vk::binding(2), vk::image_format("rgba16f")]] RWTexture2DArray<float4> output;
+
+static const uint SAMPLE_COUNT = 256;
+
+// Convolve the cubemap with the GGX lobe for one mip of the output, assuming that the view
+// direction is the same as the normal.
+[numthreads(8, 8, 1)]
+void prefilter_specular(
+    uint3 id: SV_DispatchThreadID
+) {
+    uint width;
+    uint height;
+    uint layers;
+    output.GetDimensions(width, height, layers);
+
+    if (!(id.x < width && id.y < height)) {
+        return;
+    }
+
+    float3 normal = cube_direction(id, width);
+
+    if (constant.roughness == 0.0) {
+        output[id] = float4(source.SampleLevel(samp, normal, 0.0), 1.0);
+        return;
+    }
+
+    float alpha = roughness_to_alpha(constant.roughness);
+    float source_texel_solid_angle = 4.0 * PI / (6.0 * constant.source_size * constant.source_size);
+
+    float3 sum = 0.0;
+    float total_weight = 0.0;
+
+    for (uint i = 0; i < SAMPLE_COUNT; i++) {
+        float3 half_vector = importance_sample_ggx(hammersley(i, SAMPLE_COUNT), normal, alpha);
+        float3 light = reflect(-normal, half_vector);
+        float n_dot_l = dot(normal, light);
+
+        if (n_dot_l > 0.0) {
+            // Sample from a blurrier mip when each sample covers more than a texel, to avoid
+            // the aliasing that bright spots in the source would otherwise cause.
+            float pdf = d_ggx(saturate(dot(normal, half_vector)), alpha) / 4.0;
+            float sample_solid_angle = 1.0 / (float(SAMPLE_COUNT) * pdf + 0.0001);
+            float lod = clamp(
+                0.5 * log2(sample_solid_angle / source_texel_solid_angle) + 1.0,
+                0.0,
+                constant.source_mips - 1.0
+            );
+
+            sum += source.SampleLevel(samp, light, lod) * n_dot_l;
+            total_weight += n_dot_l;
+        }
+    }
+
+    output[id] = float4(sum / total_weight, 1.0);
+}
@@ -0,0 +1,65 @@
+// Cascaded shadow maps for a directional light.
+
+// Matches `CASCADE_COUNT` in `src/shadows.rs`.
+static const uint CASCADE_COUNT = 4;
+
+// Matches `ShadowUniforms` in `src/shadows.rs`.
+struct ShadowUniforms {
+    float4x4 cascade_matrices[CASCADE_COUNT];
+    float4 cascade_splits;
+    float4 cascade_texel_sizes;
+    uint light_index;
+    float depth_bias;
+    float normal_bias;
+};
+
+// How much of a directional light reaches a point, filtered over a 3x3 grid of bilinear
+// comparisons. Points beyond the last cascade are fully lit.
+float shadow_factor(
+    Texture2DArray<float> shadow_map,
+    SamplerComparisonState shadow_sampler,
+    ShadowUniforms uniforms,
+    float3 position,
+    float3 normal,
+    float view_depth
+) {
+    uint cascade = 0;
+
+    while (cascade < CASCADE_COUNT && view_depth > uniforms.cascade_splits[cascade]) {
+        cascade++;
+    }
+
+    if (cascade == CASCADE_COUNT) {
+        return 1.0;
+    }
+
+    // Offsetting along the normal by an amount that scales with the cascade's texel size avoids
+    // most self-shadowing without the shadows detaching from their casters.
+    float3 offset_position = position + normal * uniforms.normal_bias * uniforms.cascade_texel_sizes[cascade];
+    float4 light_space = mul(uniforms.cascade_matrices[cascade], float4(offset_position, 1.0));
+    float2 uv = light_space.xy * float2(0.5, -0.5) + 0.5;
+
+    if (any(uv < 0.0) || any(uv > 1.0)) {
+        return 1.0;
+    }
+
+    uint width;
+    uint height;
+    uint layers;
+    shadow_map.GetDimensions(width, height, layers);
+    float2 texel = 1.0 / float2(width, height);
+
+    float lit = 0.0;
+
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            lit += shadow_map.SampleCmpLevelZero(
+                shadow_sampler,
+                float3(uv + float2(x, y) * texel, cascade),
+                light_space.z - uniforms.depth_bias
+            );
+        }
+    }
+
+    return lit / 9.0;
+}
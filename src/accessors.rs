@@ -140,6 +140,14 @@ pub fn read_f32x3<'a>(
                     })
                     .collect(),
             ),
+            (ComponentType::UnsignedByte, true, Some(stride)) => Cow::Owned(
+                slice
+                    .chunks(stride)
+                    .map(move |slice| {
+                        Vec3::from(std::array::from_fn(|i| unsigned_byte_to_float(slice[i])))
+                    })
+                    .collect(),
+            ),
             other => {
                 return Err(anyhow::anyhow!(
                 "{}: Unsupported combination of component type, normalized and byte stride: {:?}",
@@ -230,6 +238,17 @@ pub fn read_f32x4<'a>(
                         .collect(),
                 )
             }
+            (ComponentType::UnsignedShort, true, None | Some(8)) => {
+                let slice: &[[u16; 4]] = bytemuck::cast_slice(slice);
+                Cow::Owned(
+                    slice
+                        .iter()
+                        .map(|slice| {
+                            Vec4::from(std::array::from_fn(|i| unsigned_short_to_float(slice[i])))
+                        })
+                        .collect(),
+                )
+            }
             other => {
                 return Err(anyhow::anyhow!(
                 "{}: Unsupported combination of component type, normalized and byte stride: {:?}",
@@ -277,6 +296,15 @@ fn read_u32x4<'a>(
                     .map(|slice| UVec4::from(std::array::from_fn(|i| slice[i] as u32)))
                     .collect(),
             ),
+            (ComponentType::UnsignedShort, false, Some(8) | None) => {
+                let slice: &[u16] = bytemuck::cast_slice(slice);
+                Cow::Owned(
+                    slice
+                        .chunks(4)
+                        .map(|slice| UVec4::from(std::array::from_fn(|i| slice[i] as u32)))
+                        .collect(),
+                )
+            }
             other => {
                 return Err(anyhow::anyhow!(
                 "{}: Unsupported combination of component type, normalized and byte stride: {:?}",
@@ -387,6 +415,84 @@ impl<'a> PrimitiveReader<'a> {
         Ok(Some(read_f32x2(slice, byte_stride, accessor)?))
     }
 
+    pub fn read_tangents(&self) -> anyhow::Result<Option<Cow<'a, [Vec4]>>> {
+        let accessor_index = match self.primitive.attributes.tangent {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        let accessor =
+            self.gltf.accessors.get(accessor_index).ok_or_else(|| {
+                anyhow::anyhow!("Accessor index {} out of bounds", accessor_index)
+            })?;
+        let (slice, byte_stride) =
+            read_buffer_with_accessor(self.buffer_view_map, self.gltf, accessor)?;
+
+        Ok(Some(read_f32x4(slice, byte_stride, accessor)?))
+    }
+
+    // Colors can be RGB or RGBA. RGB colors are given an alpha of 1.
+    pub fn read_colors(&self) -> anyhow::Result<Option<Cow<'a, [Vec4]>>> {
+        let accessor_index = match self.primitive.attributes.color_0 {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        let accessor =
+            self.gltf.accessors.get(accessor_index).ok_or_else(|| {
+                anyhow::anyhow!("Accessor index {} out of bounds", accessor_index)
+            })?;
+        let (slice, byte_stride) =
+            read_buffer_with_accessor(self.buffer_view_map, self.gltf, accessor)?;
+
+        if accessor.accessor_type.num_components() == 3 {
+            let colors = read_f32x3(slice, byte_stride, accessor)?;
+            Ok(Some(Cow::Owned(
+                colors.iter().map(|color| color.extend(1.0)).collect(),
+            )))
+        } else {
+            Ok(Some(read_f32x4(slice, byte_stride, accessor)?))
+        }
+    }
+
+    pub fn read_morph_target_positions(
+        &self,
+        target: usize,
+    ) -> anyhow::Result<Option<Cow<'a, [Vec3]>>> {
+        let accessor_index = match self.primitive.targets.get(target).and_then(|t| t.position) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        let accessor =
+            self.gltf.accessors.get(accessor_index).ok_or_else(|| {
+                anyhow::anyhow!("Accessor index {} out of bounds", accessor_index)
+            })?;
+        let (slice, byte_stride) =
+            read_buffer_with_accessor(self.buffer_view_map, self.gltf, accessor)?;
+
+        Ok(Some(read_f32x3(slice, byte_stride, accessor)?))
+    }
+
+    pub fn read_morph_target_normals(
+        &self,
+        target: usize,
+    ) -> anyhow::Result<Option<Cow<'a, [Vec3]>>> {
+        let accessor_index = match self.primitive.targets.get(target).and_then(|t| t.normal) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        let accessor =
+            self.gltf.accessors.get(accessor_index).ok_or_else(|| {
+                anyhow::anyhow!("Accessor index {} out of bounds", accessor_index)
+            })?;
+        let (slice, byte_stride) =
+            read_buffer_with_accessor(self.buffer_view_map, self.gltf, accessor)?;
+
+        Ok(Some(read_f32x3(slice, byte_stride, accessor)?))
+    }
+
     pub fn read_joints(&self) -> anyhow::Result<Option<Cow<'a, [UVec4]>>> {
         let accessor_index = match self.primitive.attributes.joints_0 {
             Some(index) => index,
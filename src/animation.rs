@@ -0,0 +1,511 @@
+use crate::accessors::{read_buffer_with_accessor, read_f32, read_f32x3, read_f32x4};
+use glam::{Mat4, Quat, Vec3};
+use goth_gltf::default_extensions::Extensions;
+use std::collections::HashMap;
+use std::ops::{Add, Mul, Range};
+
+#[derive(Clone, Copy)]
+struct NodeTransform {
+    translation: Vec3,
+    rotation: Quat,
+    scale: Vec3,
+}
+
+impl NodeTransform {
+    fn from_node(node: &goth_gltf::Node<Extensions>) -> Self {
+        match node.matrix {
+            Some(matrix) => {
+                let (scale, rotation, translation) =
+                    Mat4::from_cols_array(&matrix).to_scale_rotation_translation();
+
+                Self {
+                    translation,
+                    rotation,
+                    scale,
+                }
+            }
+            None => Self {
+                translation: Vec3::from(node.translation),
+                rotation: Quat::from_array(node.rotation),
+                scale: Vec3::from(node.scale),
+            },
+        }
+    }
+
+    fn as_matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+}
+
+trait Keyframe: Copy + Add<Output = Self> + Mul<f32, Output = Self> {
+    fn interpolate(self, other: Self, t: f32) -> Self;
+
+    // Fix up the result of cubic spline interpolation.
+    fn finish(self) -> Self {
+        self
+    }
+}
+
+impl Keyframe for f32 {
+    fn interpolate(self, other: Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Keyframe for Vec3 {
+    fn interpolate(self, other: Self, t: f32) -> Self {
+        self.lerp(other, t)
+    }
+}
+
+impl Keyframe for Quat {
+    fn interpolate(self, other: Self, t: f32) -> Self {
+        self.slerp(other, t)
+    }
+
+    fn finish(self) -> Self {
+        self.normalize()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Interpolation {
+    Step,
+    Linear,
+    CubicSpline,
+}
+
+// Find the keyframe value at `time`, clamping to the first and last keyframes.
+fn sample<T: Keyframe>(times: &[f32], values: &[T], interpolation: Interpolation, time: f32) -> T {
+    // Cubic spline keyframes are stored as (in tangent, value, out tangent).
+    let value = |index: usize| match interpolation {
+        Interpolation::CubicSpline => values[index * 3 + 1],
+        _ => values[index],
+    };
+
+    let next = times.partition_point(|&keyframe_time| keyframe_time <= time);
+
+    if next == 0 {
+        return value(0);
+    }
+
+    if next == times.len() {
+        return value(times.len() - 1);
+    }
+
+    let previous = next - 1;
+    let delta = times[next] - times[previous];
+
+    // Keyframe times should be increasing, but don't divide by zero if they aren't.
+    if delta <= 0.0 {
+        return value(previous);
+    }
+
+    let t = (time - times[previous]) / delta;
+
+    match interpolation {
+        Interpolation::Step => value(previous),
+        Interpolation::Linear => value(previous).interpolate(value(next), t),
+        Interpolation::CubicSpline => {
+            let out_tangent = values[previous * 3 + 2];
+            let in_tangent = values[next * 3];
+
+            let t2 = t * t;
+            let t3 = t2 * t;
+
+            (value(previous) * (2.0 * t3 - 3.0 * t2 + 1.0)
+                + out_tangent * ((t3 - 2.0 * t2 + t) * delta)
+                + value(next) * (-2.0 * t3 + 3.0 * t2)
+                + in_tangent * ((t3 - t2) * delta))
+                .finish()
+        }
+    }
+}
+
+enum Property {
+    Translation(Vec<Vec3>),
+    Rotation(Vec<Quat>),
+    Scale(Vec<Vec3>),
+    // The keyframes of each morph target weight of a mesh.
+    Weights { mesh: usize, values: Vec<Vec<f32>> },
+}
+
+impl Property {
+    // Whether each list of keyframe values has `len` values.
+    fn has_len(&self, len: usize) -> bool {
+        match self {
+            Self::Translation(values) | Self::Scale(values) => values.len() == len,
+            Self::Rotation(values) => values.len() == len,
+            Self::Weights { values, .. } => values.iter().all(|values| values.len() == len),
+        }
+    }
+}
+
+struct Channel {
+    node: usize,
+    interpolation: Interpolation,
+    times: Vec<f32>,
+    property: Property,
+}
+
+struct Animation {
+    channels: Vec<Channel>,
+    duration: f32,
+}
+
+struct Skin {
+    joints: Vec<usize>,
+    inverse_bind_matrices: Vec<Mat4>,
+}
+
+// The offset of a morph target vertex from the base vertex.
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+pub struct MorphTargetDelta {
+    pub position: Vec3,
+    pub normal: Vec3,
+}
+
+pub struct MorphTargets {
+    // The deltas of the first target, with the rest following it one after the other.
+    pub deltas_start: u32,
+    pub count: u32,
+    pub mesh: usize,
+}
+
+// A run of vertices, morphed and/or skinned from their base pose in `AnimatedVertexBuffers` into
+// `VertexBuffers` by the `animate_vertices` node.
+pub struct AnimatedPrimitive {
+    // The base pose vertices in `AnimatedVertexBuffers`.
+    pub source: Range<u32>,
+    // The first vertex in `VertexBuffers` to write to.
+    pub dest_start: u32,
+    pub skin: Option<usize>,
+    pub morph_targets: Option<MorphTargets>,
+}
+
+// The node hierarchy, skins, morph target weights and animations of an animated model. Every
+// instance of the model shares the same pose.
+pub struct AnimationState {
+    parents: Vec<Option<usize>>,
+    // Node indices ordered so that parents come before their children.
+    node_order: Vec<usize>,
+    local_transforms: Vec<NodeTransform>,
+    global_transforms: Vec<Mat4>,
+    skins: Vec<Skin>,
+    animations: Vec<Animation>,
+    time: f32,
+    pub primitives: Vec<AnimatedPrimitive>,
+    // The joint matrices of every skin, one after the other.
+    pub joint_matrices: Vec<Mat4>,
+    // The offset of each skin into `joint_matrices`.
+    pub skin_offsets: Vec<u32>,
+    // The morph target weights of every mesh, one after the other.
+    pub morph_weights: Vec<f32>,
+    // The offset of each mesh into `morph_weights`.
+    pub morph_weight_offsets: Vec<u32>,
+}
+
+fn read_accessor<'a, T: Clone>(
+    gltf: &'a goth_gltf::Gltf<Extensions>,
+    buffer_view_map: &'a HashMap<usize, Vec<u8>>,
+    accessor_index: usize,
+    read: fn(
+        &'a [u8],
+        Option<usize>,
+        &goth_gltf::Accessor,
+    ) -> anyhow::Result<std::borrow::Cow<'a, [T]>>,
+) -> anyhow::Result<Vec<T>> {
+    let accessor = gltf
+        .accessors
+        .get(accessor_index)
+        .ok_or_else(|| anyhow::anyhow!("Accessor index {} out of bounds", accessor_index))?;
+    let (slice, byte_stride) = read_buffer_with_accessor(buffer_view_map, gltf, accessor)?;
+
+    Ok(read(slice, byte_stride, accessor)?.into_owned())
+}
+
+impl AnimationState {
+    pub fn new(
+        gltf: &goth_gltf::Gltf<Extensions>,
+        buffer_view_map: &HashMap<usize, Vec<u8>>,
+        primitives: Vec<AnimatedPrimitive>,
+    ) -> anyhow::Result<Self> {
+        let mut parents = vec![None; gltf.nodes.len()];
+
+        for (index, node) in gltf.nodes.iter().enumerate() {
+            for &child in &node.children {
+                if let Some(parent) = parents.get_mut(child) {
+                    *parent = Some(index);
+                }
+            }
+        }
+
+        let mut node_order = Vec::with_capacity(gltf.nodes.len());
+        let mut stack: Vec<usize> = (0..gltf.nodes.len())
+            .filter(|&index| parents[index].is_none())
+            .collect();
+
+        let mut visited = vec![false; gltf.nodes.len()];
+
+        while let Some(index) = stack.pop() {
+            if std::mem::replace(&mut visited[index], true) {
+                return Err(anyhow::anyhow!(
+                    "Node {} is reached more than once in the node hierarchy",
+                    index
+                ));
+            }
+
+            node_order.push(index);
+            stack.extend(
+                gltf.nodes[index]
+                    .children
+                    .iter()
+                    .filter(|&&child| child < gltf.nodes.len()),
+            );
+        }
+
+        let skins = gltf
+            .skins
+            .iter()
+            .map(|skin| {
+                let inverse_bind_matrices = match skin.inverse_bind_matrices {
+                    Some(accessor) => read_accessor(gltf, buffer_view_map, accessor, read_f32)?
+                        .chunks_exact(16)
+                        .map(Mat4::from_cols_slice)
+                        .collect(),
+                    None => vec![Mat4::IDENTITY; skin.joints.len()],
+                };
+
+                Ok(Skin {
+                    joints: skin.joints.clone(),
+                    inverse_bind_matrices,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut morph_weights = Vec::new();
+        let mut morph_weight_offsets = Vec::with_capacity(gltf.meshes.len());
+        let mut mesh_num_targets = Vec::with_capacity(gltf.meshes.len());
+
+        for mesh in &gltf.meshes {
+            morph_weight_offsets.push(morph_weights.len() as u32);
+
+            // All the primitives of a mesh must have the same number of targets.
+            let num_targets = mesh
+                .primitives
+                .first()
+                .map(|primitive| primitive.targets.len())
+                .unwrap_or(0);
+
+            mesh_num_targets.push(num_targets);
+
+            if mesh.weights.len() == num_targets {
+                morph_weights.extend_from_slice(&mesh.weights);
+            } else {
+                morph_weights.extend(std::iter::repeat(0.0).take(num_targets));
+            }
+        }
+
+        let mut animations = Vec::new();
+
+        for animation in &gltf.animations {
+            let mut channels = Vec::new();
+
+            for channel in &animation.channels {
+                let node = match channel.target.node {
+                    Some(node) => node,
+                    None => continue,
+                };
+
+                let sampler = animation.samplers.get(channel.sampler).ok_or_else(|| {
+                    anyhow::anyhow!("Sampler index {} out of bounds", channel.sampler)
+                })?;
+
+                let output = sampler.output;
+
+                let property = match channel.target.path {
+                    goth_gltf::TargetPath::Translation => Property::Translation(read_accessor(
+                        gltf,
+                        buffer_view_map,
+                        output,
+                        read_f32x3,
+                    )?),
+                    goth_gltf::TargetPath::Rotation => Property::Rotation(
+                        read_accessor(gltf, buffer_view_map, output, read_f32x4)?
+                            .into_iter()
+                            .map(Quat::from_vec4)
+                            .collect(),
+                    ),
+                    goth_gltf::TargetPath::Scale => {
+                        Property::Scale(read_accessor(gltf, buffer_view_map, output, read_f32x3)?)
+                    }
+                    goth_gltf::TargetPath::Weights => {
+                        let mesh = match gltf.nodes.get(node).and_then(|node| node.mesh) {
+                            Some(mesh) => mesh,
+                            None => continue,
+                        };
+
+                        let num_targets = mesh_num_targets.get(mesh).copied().unwrap_or(0);
+
+                        if num_targets == 0 {
+                            continue;
+                        }
+
+                        let weights = read_accessor(gltf, buffer_view_map, output, read_f32)?;
+
+                        // Weights are stored keyframe by keyframe, so split them up by target.
+                        let values = (0..num_targets)
+                            .map(|target| {
+                                weights
+                                    .iter()
+                                    .skip(target)
+                                    .step_by(num_targets)
+                                    .copied()
+                                    .collect()
+                            })
+                            .collect();
+
+                        Property::Weights { mesh, values }
+                    }
+                };
+
+                let times = read_accessor(gltf, buffer_view_map, sampler.input, read_f32)?;
+
+                if times.is_empty() {
+                    continue;
+                }
+
+                let interpolation = match sampler.interpolation {
+                    goth_gltf::Interpolation::Step => Interpolation::Step,
+                    goth_gltf::Interpolation::Linear => Interpolation::Linear,
+                    goth_gltf::Interpolation::CubicSpline => Interpolation::CubicSpline,
+                };
+
+                let values_per_keyframe = match interpolation {
+                    Interpolation::CubicSpline => 3,
+                    _ => 1,
+                };
+
+                if !property.has_len(times.len() * values_per_keyframe) {
+                    return Err(anyhow::anyhow!(
+                        "Animation sampler {} has the wrong number of output values for {} keyframes",
+                        channel.sampler,
+                        times.len()
+                    ));
+                }
+
+                channels.push(Channel {
+                    node,
+                    interpolation,
+                    times,
+                    property,
+                });
+            }
+
+            let duration = channels
+                .iter()
+                .filter_map(|channel| channel.times.last().copied())
+                .fold(0.0, f32::max);
+
+            animations.push(Animation { channels, duration });
+        }
+
+        let mut skin_offsets = Vec::with_capacity(skins.len());
+        let mut num_joints = 0;
+
+        for skin in &skins {
+            skin_offsets.push(num_joints);
+            num_joints += skin.joints.len() as u32;
+        }
+
+        let mut state = Self {
+            parents,
+            node_order,
+            local_transforms: gltf.nodes.iter().map(NodeTransform::from_node).collect(),
+            global_transforms: vec![Mat4::IDENTITY; gltf.nodes.len()],
+            skins,
+            animations,
+            time: 0.0,
+            primitives,
+            joint_matrices: vec![Mat4::IDENTITY; num_joints as usize],
+            skin_offsets,
+            morph_weights,
+            morph_weight_offsets,
+        };
+
+        state.update(0.0);
+
+        Ok(state)
+    }
+
+    // Advance the first animation (looping it) and recompute the joint matrices and morph target
+    // weights.
+    pub fn update(&mut self, delta_time: f32) {
+        if let Some(animation) = self.animations.first() {
+            if animation.duration > 0.0 {
+                self.time = (self.time + delta_time) % animation.duration;
+            }
+
+            for channel in &animation.channels {
+                let times = &channel.times;
+                let interpolation = channel.interpolation;
+
+                if let Property::Weights { mesh, values } = &channel.property {
+                    let offset = self.morph_weight_offsets[*mesh] as usize;
+
+                    for (target, values) in values.iter().enumerate() {
+                        self.morph_weights[offset + target] =
+                            sample(times, values, interpolation, self.time);
+                    }
+
+                    continue;
+                }
+
+                let transform = match self.local_transforms.get_mut(channel.node) {
+                    Some(transform) => transform,
+                    None => continue,
+                };
+
+                match &channel.property {
+                    Property::Translation(values) => {
+                        transform.translation = sample(times, values, interpolation, self.time)
+                    }
+                    Property::Rotation(values) => {
+                        transform.rotation = sample(times, values, interpolation, self.time)
+                    }
+                    Property::Scale(values) => {
+                        transform.scale = sample(times, values, interpolation, self.time)
+                    }
+                    Property::Weights { .. } => {}
+                }
+            }
+        }
+
+        for &node in &self.node_order {
+            let local = self.local_transforms[node].as_matrix();
+
+            self.global_transforms[node] = match self.parents[node] {
+                Some(parent) => self.global_transforms[parent] * local,
+                None => local,
+            };
+        }
+
+        for (skin, &offset) in self.skins.iter().zip(&self.skin_offsets) {
+            for (i, (&joint, inverse_bind_matrix)) in skin
+                .joints
+                .iter()
+                .zip(&skin.inverse_bind_matrices)
+                .enumerate()
+            {
+                let global_transform = self
+                    .global_transforms
+                    .get(joint)
+                    .copied()
+                    .unwrap_or(Mat4::IDENTITY);
+
+                self.joint_matrices[offset as usize + i] = global_transform * *inverse_bind_matrix;
+            }
+        }
+    }
+}
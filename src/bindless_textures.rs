@@ -1,22 +1,152 @@
-use std::ops::Range;
+use wgpu::util::DeviceExt;
+
+// The number of sampled textures bound in the same shader stage as the bindless array. Binding
+// arrays count towards the per-stage limit with their full length, so these are left out of it.
+// `moon.hlsl` binds the specular, irradiance and BRDF lookup textures of the image-based lighting,
+// along with the shadow maps.
+const RESERVED_SAMPLED_TEXTURES: u32 = 4;
+
+// The length of the bindless array, which is the number of slots in `BindlessTextures` and the
+// length that runtime-sized arrays in shaders are reflected with.
+pub fn array_length(limits: &wgpu::Limits) -> u32 {
+    limits.max_sampled_textures_per_shader_stage - RESERVED_SAMPLED_TEXTURES
+}
+
+// A handle to a slot in `BindlessTextures`. Handles to removed textures are detected by their
+// generation, so that a reused slot can't be modified through a stale handle.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TextureHandle {
+    index: u32,
+    generation: u32,
+}
+
+impl TextureHandle {
+    // The index of the texture in the bindless texture array.
+    pub fn index(self) -> u32 {
+        self.index
+    }
+}
+
+struct Slot {
+    generation: u32,
+    occupied: bool,
+    view: wgpu::TextureView,
+}
 
 pub struct BindlessTextures {
-    views: Vec<wgpu::TextureView>,
+    slots: Vec<Slot>,
+    free_slots: Vec<u32>,
+    // Bound in freed slots so that every slot in the array always has a valid view.
+    null_texture: wgpu::Texture,
+    capacity: u32,
+    // Incremented whenever the view in any slot changes, so that bind groups containing the
+    // array can be recreated.
+    version: u64,
 }
 
 impl BindlessTextures {
-    pub fn new() -> Self {
-        Self { views: Vec::new() }
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        Self {
+            slots: Vec::new(),
+            free_slots: Vec::new(),
+            null_texture: device.create_texture_with_data(
+                queue,
+                &wgpu::TextureDescriptor {
+                    label: Some("null texture"),
+                    size: wgpu::Extent3d {
+                        width: 1,
+                        height: 1,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: wgpu::TextureFormat::Rgba8Unorm,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                },
+                &[0; 4],
+            ),
+            capacity: array_length(&device.limits()),
+            version: 0,
+        }
+    }
+
+    pub fn insert(&mut self, view: wgpu::TextureView) -> anyhow::Result<TextureHandle> {
+        let index = match self.free_slots.pop() {
+            Some(index) => index,
+            None => {
+                if self.slots.len() as u32 >= self.capacity {
+                    return Err(anyhow::anyhow!(
+                        "Bindless texture array is full ({} textures)",
+                        self.capacity
+                    ));
+                }
+
+                self.slots.push(Slot {
+                    generation: 0,
+                    occupied: false,
+                    view: self.null_view(),
+                });
+
+                self.slots.len() as u32 - 1
+            }
+        };
+
+        let slot = &mut self.slots[index as usize];
+        slot.occupied = true;
+        slot.view = view;
+        self.version += 1;
+
+        Ok(TextureHandle {
+            index,
+            generation: slot.generation,
+        })
+    }
+
+    // Free a slot, returning the view that was in it.
+    pub fn remove(&mut self, handle: TextureHandle) -> Option<wgpu::TextureView> {
+        let null_view = self.null_view();
+        let slot = self.slot_mut(handle)?;
+
+        slot.occupied = false;
+        slot.generation += 1;
+        let view = std::mem::replace(&mut slot.view, null_view);
+
+        self.free_slots.push(handle.index);
+        self.version += 1;
+
+        Some(view)
+    }
+
+    // Swap the view in a slot, returning the previous one. Returns `None` and drops the new view
+    // if the handle is stale.
+    pub fn replace(
+        &mut self,
+        handle: TextureHandle,
+        view: wgpu::TextureView,
+    ) -> Option<wgpu::TextureView> {
+        let slot = self.slot_mut(handle)?;
+        let previous = std::mem::replace(&mut slot.view, view);
+        self.version += 1;
+        Some(previous)
+    }
+
+    fn slot_mut(&mut self, handle: TextureHandle) -> Option<&mut Slot> {
+        self.slots
+            .get_mut(handle.index as usize)
+            .filter(|slot| slot.occupied && slot.generation == handle.generation)
+    }
+
+    fn null_view(&self) -> wgpu::TextureView {
+        self.null_texture.create_view(&Default::default())
     }
 
-    pub fn push(&mut self, mut views: Vec<wgpu::TextureView>) -> Range<u32> {
-        let start = self.views.len() as u32;
-        self.views.extend(views.drain(..));
-        let end = self.views.len() as u32;
-        start..end
+    pub fn version(&self) -> u64 {
+        self.version
     }
 
     pub fn texture_view_array(&self) -> Vec<&wgpu::TextureView> {
-        self.views.iter().collect()
+        self.slots.iter().map(|slot| &slot.view).collect()
     }
 }
@@ -1,6 +1,7 @@
 use arc_swap::ArcSwap;
 use std::mem::size_of;
 use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
 use glam::{UVec4, Vec2, Vec3, Vec4};
@@ -12,6 +13,9 @@ pub struct VecGpuBuffer<T: bytemuck::Pod> {
     usage: wgpu::BufferUsages,
     _phantom: std::marker::PhantomData<T>,
     label: &'static str,
+    // Incremented whenever `buffer` is reallocated, so that bind groups using it can be
+    // recreated.
+    version: u64,
 }
 
 impl<T: bytemuck::Pod> VecGpuBuffer<T> {
@@ -37,6 +41,7 @@ impl<T: bytemuck::Pod> VecGpuBuffer<T> {
             usage,
             label,
             _phantom: Default::default(),
+            version: 0,
         }
     }
 
@@ -97,12 +102,17 @@ impl<T: bytemuck::Pod> VecGpuBuffer<T> {
 
         self.buffer = new_buffer;
         self.capacity = new_capacity;
+        self.version += 1;
     }
 
     #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> u32 {
         self.offset
     }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
 }
 
 pub struct AllocatedBuffer<T> {
@@ -111,13 +121,12 @@ pub struct AllocatedBuffer<T> {
     usage: wgpu::BufferUsages,
     _phantom: std::marker::PhantomData<T>,
     label: &'static str,
+    // Incremented whenever `buffer` is reallocated, so that bind groups using it can be
+    // recreated.
+    version: AtomicU64,
 }
 
 impl<T: bytemuck::Pod> AllocatedBuffer<T> {
-    fn size_in_bytes(size: u32) -> u64 {
-        size as u64 * size_of::<T>() as u64
-    }
-
     pub fn new(
         capacity: u32,
         device: &wgpu::Device,
@@ -126,20 +135,30 @@ impl<T: bytemuck::Pod> AllocatedBuffer<T> {
     ) -> Self {
         Self {
             allocator: parking_lot::Mutex::new(range_alloc::RangeAllocator::new(0..capacity)),
-            buffer: arc_swap::ArcSwap::from_pointee(device.create_buffer(
-                &wgpu::BufferDescriptor {
-                    label: Some(label),
-                    size: Self::size_in_bytes(capacity),
-                    usage: usage | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
-                    mapped_at_creation: false,
-                },
+            buffer: ArcSwap::from(create_buffer(
+                device,
+                label,
+                capacity,
+                size_of::<T>(),
+                usage,
             )),
             label,
             usage,
             _phantom: Default::default(),
+            version: AtomicU64::new(0),
         }
     }
 
+    fn create_buffers(&self, device: &wgpu::Device, capacity: u32) -> Vec<Arc<wgpu::Buffer>> {
+        vec![create_buffer(
+            device,
+            self.label,
+            capacity,
+            size_of::<T>(),
+            self.usage,
+        )]
+    }
+
     pub fn insert(
         &self,
         values: &[T],
@@ -149,6 +168,11 @@ impl<T: bytemuck::Pod> AllocatedBuffer<T> {
     ) -> (Range<u32>, Option<Arc<wgpu::Buffer>>) {
         let length = values.len() as u32;
 
+        // The allocator can't hand out empty ranges.
+        if length == 0 {
+            return (0..0, None);
+        }
+
         // Use the allocator to find a range in the buffer to write to,
         // resizing the buffer in needed and returning the correct buffer to write to
         // (as `ArcSwap::load` does not always return the newest value).
@@ -158,76 +182,58 @@ impl<T: bytemuck::Pod> AllocatedBuffer<T> {
             match allocator.allocate_range(length) {
                 Ok(range) => (self.buffer.load_full(), range, false),
                 Err(_) => {
-                    let new_buffer = Self::resize(
+                    let mut new_buffers = resize_buffers(
                         &mut allocator,
-                        &self.buffer,
-                        self.usage,
+                        &[(&self.buffer, size_of::<T>())],
                         self.label,
                         length,
-                        device,
                         command_encoder,
+                        |capacity| self.create_buffers(device, capacity),
                     );
+                    self.version.fetch_add(1, Ordering::Relaxed);
                     let range = allocator.allocate_range(length).expect("just resized");
-                    (new_buffer, range, true)
+                    (new_buffers.remove(0), range, true)
                 }
             }
         };
 
         queue.write_buffer(
             &buffer,
-            Self::size_in_bytes(range.start),
+            size_in_bytes(range.start, size_of::<T>()),
             bytemuck::cast_slice(values),
         );
 
         (range, if resized { Some(buffer) } else { None })
     }
 
-    fn resize(
-        allocator: &mut range_alloc::RangeAllocator<u32>,
-        buffer: &ArcSwap<wgpu::Buffer>,
-        usage: wgpu::BufferUsages,
-        label: &'static str,
-        required_capacity: u32,
-        device: &wgpu::Device,
-        command_encoder: &mut wgpu::CommandEncoder,
-    ) -> Arc<wgpu::Buffer> {
-        let copy_range = allocator
-            .allocated_ranges()
-            .last()
-            .map(|range| range.end)
-            .unwrap_or(0);
-
-        let old_capacity = allocator.initial_range().end;
-
-        let new_capacity = (old_capacity + required_capacity).max(old_capacity * 2);
-
-        log::info!(
-            "Growing {} from {} to {}",
-            label,
-            old_capacity,
-            new_capacity
-        );
-
-        allocator.grow_to(new_capacity);
+    // Free a range returned by `insert` so that it can be reused.
+    pub fn remove(&self, range: Range<u32>) {
+        free_range(&self.allocator, range);
+    }
 
-        let new_buffer = Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some(label),
-            size: Self::size_in_bytes(new_capacity),
-            usage: usage | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
-            mapped_at_creation: false,
-        }));
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Relaxed)
+    }
 
-        command_encoder.copy_buffer_to_buffer(
-            &buffer.load(),
-            0,
-            &new_buffer,
-            0,
-            Self::size_in_bytes(copy_range),
+    // Move all the live ranges to the start of a new buffer, closing the holes left by `remove`.
+    pub fn compact(
+        &self,
+        device: &wgpu::Device,
+        command_encoder: &mut wgpu::CommandEncoder,
+    ) -> Remapping {
+        let remapping = compact_buffers(
+            &mut self.allocator.lock(),
+            &[(&self.buffer, size_of::<T>())],
+            command_encoder,
+            |capacity| self.create_buffers(device, capacity),
         );
+        self.version.fetch_add(1, Ordering::Relaxed);
 
-        buffer.store(new_buffer.clone());
+        remapping
+    }
 
-        new_buffer
+    pub fn stats(&self) -> AllocatorStats {
+        AllocatorStats::new(&self.allocator.lock())
     }
 }
 
@@ -258,105 +264,189 @@ impl IndexBuffer {
         range
     }
 
+    pub fn remove(&self, range: Range<u32>) {
+        self.inner.remove(range);
+    }
+
+    pub fn compact(
+        &self,
+        device: &wgpu::Device,
+        command_encoder: &mut wgpu::CommandEncoder,
+    ) -> Remapping {
+        self.inner.compact(device, command_encoder)
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        self.inner.stats()
+    }
+
     pub fn buffer(&self) -> arc_swap::Guard<Arc<wgpu::Buffer>> {
         self.inner.buffer.load()
     }
 }
 
-pub struct RawVertexBuffers<T> {
-    pub position: T,
-    pub normal: T,
-    pub uv: T,
-    pub material_id: T,
+// A set of per-vertex attribute streams that are each stored in their own buffer, but allocated
+// together. Implemented by `soa_layout!`.
+pub trait SoaLayout {
+    // A slice of values for every stream.
+    type Data<'a>;
+    // The label and element size of each stream, in field order.
+    const STREAMS: &'static [(&'static str, usize)];
+
+    fn from_buffers(buffers: Vec<Arc<wgpu::Buffer>>) -> Self;
+    fn buffers(&self) -> Vec<&ArcSwap<wgpu::Buffer>>;
+    fn data_len(data: &Self::Data<'_>) -> usize;
+    fn data_bytes<'a>(data: &Self::Data<'a>) -> Vec<&'a [u8]>;
 }
 
-impl RawVertexBuffers<ArcSwap<wgpu::Buffer>> {
-    pub fn load(&self) -> RawVertexBuffers<arc_swap::Guard<Arc<wgpu::Buffer>>> {
-        RawVertexBuffers {
-            position: self.position.load(),
-            normal: self.normal.load(),
-            uv: self.uv.load(),
-            material_id: self.material_id.load(),
+// Declare a `$raw<T>` struct with a `T` for each stream, and a `$data` struct with a slice for
+// each stream to insert.
+macro_rules! soa_layout {
+    ($raw:ident, $data:ident { $($field:ident: $ty:ty = $label:literal),* $(,)? }) => {
+        pub struct $raw<T> {
+            $(pub $field: T,)*
         }
-    }
+
+        impl $raw<ArcSwap<wgpu::Buffer>> {
+            pub fn load(&self) -> $raw<arc_swap::Guard<Arc<wgpu::Buffer>>> {
+                $raw {
+                    $($field: self.$field.load(),)*
+                }
+            }
+        }
+
+        pub struct $data<'a> {
+            $(pub $field: &'a [$ty],)*
+        }
+
+        impl SoaLayout for $raw<ArcSwap<wgpu::Buffer>> {
+            type Data<'a> = $data<'a>;
+            const STREAMS: &'static [(&'static str, usize)] = &[$(($label, size_of::<$ty>()),)*];
+
+            fn from_buffers(buffers: Vec<Arc<wgpu::Buffer>>) -> Self {
+                let mut buffers = buffers.into_iter();
+
+                Self {
+                    $($field: ArcSwap::from(buffers.next().unwrap()),)*
+                }
+            }
+
+            fn buffers(&self) -> Vec<&ArcSwap<wgpu::Buffer>> {
+                vec![$(&self.$field,)*]
+            }
+
+            fn data_len(data: &Self::Data<'_>) -> usize {
+                let lengths = [$(data.$field.len(),)*];
+                debug_assert!(lengths.iter().all(|&length| length == lengths[0]));
+                lengths[0]
+            }
+
+            fn data_bytes<'a>(data: &Self::Data<'a>) -> Vec<&'a [u8]> {
+                vec![$(bytemuck::cast_slice(data.$field),)*]
+            }
+        }
+    };
 }
 
-pub struct VertexBuffers {
+soa_layout!(
+    RawVertexBuffers,
+    VertexData {
+        position: Vec3 = "position buffer",
+        normal: Vec3 = "normal buffer",
+        uv: Vec2 = "uv buffer",
+        second_uv: Vec2 = "second uv buffer",
+        // The xyz direction, with the sign of the bitangent in w.
+        tangent: Vec4 = "tangent buffer",
+        color: Vec4 = "color buffer",
+        material_id: u32 = "material id buffer",
+    }
+);
+
+soa_layout!(
+    RawAnimatedVertexBuffers,
+    AnimatedVertexData {
+        position: Vec3 = "animated position buffer",
+        normal: Vec3 = "animated normal buffer",
+        uv: Vec2 = "animated uv buffer",
+        tangent: Vec4 = "animated tangent buffer",
+        joint_indices: UVec4 = "joint indices buffer",
+        joint_weights: Vec4 = "joint weights buffer",
+    }
+);
+
+pub type VertexBuffers = SoaStore<RawVertexBuffers<ArcSwap<wgpu::Buffer>>>;
+// Base pose vertices of skinned and morphed meshes.
+pub type AnimatedVertexBuffers = SoaStore<RawAnimatedVertexBuffers<ArcSwap<wgpu::Buffer>>>;
+
+// A structure-of-arrays vertex store, where the same range of vertices is allocated in every
+// stream of the layout.
+pub struct SoaStore<L> {
     allocator: parking_lot::Mutex<range_alloc::RangeAllocator<u32>>,
-    pub buffers: RawVertexBuffers<ArcSwap<wgpu::Buffer>>,
+    pub buffers: L,
+    label: &'static str,
 }
 
-impl VertexBuffers {
-    pub fn new(capacity: u32, device: &wgpu::Device) -> Self {
+impl<L: SoaLayout> SoaStore<L> {
+    pub fn new(capacity: u32, device: &wgpu::Device, label: &'static str) -> Self {
         Self {
             allocator: parking_lot::Mutex::new(range_alloc::RangeAllocator::new(0..capacity)),
-            buffers: RawVertexBuffers {
-                position: ArcSwap::from(create_buffer(
-                    device,
-                    "position buffer",
-                    capacity,
-                    size_of::<Vec3>(),
-                )),
-                normal: ArcSwap::from(create_buffer(
-                    device,
-                    "normal buffer",
-                    capacity,
-                    size_of::<Vec3>(),
-                )),
-                uv: ArcSwap::from(create_buffer(
-                    device,
-                    "uv buffer",
-                    capacity,
-                    size_of::<Vec2>(),
-                )),
-                material_id: ArcSwap::from(create_buffer(
+            buffers: L::from_buffers(Self::create_buffers(device, capacity)),
+            label,
+        }
+    }
+
+    fn create_buffers(device: &wgpu::Device, capacity: u32) -> Vec<Arc<wgpu::Buffer>> {
+        L::STREAMS
+            .iter()
+            .map(|&(label, size_of_field)| {
+                // Storage so that the skinning shader can read from and write to them.
+                create_buffer(
                     device,
-                    "material id buffer",
+                    label,
                     capacity,
-                    size_of::<u32>(),
-                )),
-            },
-        }
+                    size_of_field,
+                    wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+                )
+            })
+            .collect()
     }
 
-    #[allow(clippy::too_many_arguments)]
     pub fn insert(
         &self,
-        positions: &[Vec3],
-        normals: &[Vec3],
-        uvs: &[Vec2],
-        material_ids: &[u32],
+        data: L::Data<'_>,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         command_encoder: &mut wgpu::CommandEncoder,
     ) -> Range<u32> {
-        let length = positions.len() as u32;
+        let length = L::data_len(&data) as u32;
 
-        debug_assert_eq!(positions.len(), normals.len());
-        debug_assert_eq!(positions.len(), uvs.len());
-        debug_assert_eq!(positions.len(), material_ids.len());
+        // The allocator can't hand out empty ranges.
+        if length == 0 {
+            return 0..0;
+        }
 
         let (buffers, range) = {
             let mut allocator = self.allocator.lock();
 
             match allocator.allocate_range(length) {
                 Ok(range) => {
-                    let buffers = RawVertexBuffers {
-                        position: self.buffers.position.load_full(),
-                        normal: self.buffers.normal.load_full(),
-                        uv: self.buffers.uv.load_full(),
-                        material_id: self.buffers.material_id.load_full(),
-                    };
+                    let buffers = self
+                        .buffers
+                        .buffers()
+                        .iter()
+                        .map(|buffer| buffer.load_full())
+                        .collect::<Vec<_>>();
 
                     (buffers, range)
                 }
                 Err(_) => {
-                    let new_buffers = Self::resize(
+                    let new_buffers = resize_buffers(
                         &mut allocator,
-                        &self.buffers,
+                        &self.streams(),
+                        self.label,
                         length,
-                        device,
                         command_encoder,
+                        |capacity| Self::create_buffers(device, capacity),
                     );
                     let range = allocator.allocate_range(length).expect("just resized");
                     (new_buffers, range)
@@ -364,365 +454,285 @@ impl VertexBuffers {
             }
         };
 
-        queue.write_buffer(
-            &buffers.position,
-            size_in_bytes(range.start, size_of::<Vec3>()),
-            bytemuck::cast_slice(positions),
-        );
-
-        queue.write_buffer(
-            &buffers.normal,
-            size_in_bytes(range.start, size_of::<Vec3>()),
-            bytemuck::cast_slice(normals),
-        );
+        for ((buffer, bytes), &(_, size_of_field)) in
+            buffers.iter().zip(L::data_bytes(&data)).zip(L::STREAMS)
+        {
+            queue.write_buffer(buffer, size_in_bytes(range.start, size_of_field), bytes);
+        }
 
-        queue.write_buffer(
-            &buffers.uv,
-            size_in_bytes(range.start, size_of::<Vec2>()),
-            bytemuck::cast_slice(uvs),
-        );
+        range
+    }
 
-        queue.write_buffer(
-            &buffers.material_id,
-            size_in_bytes(range.start, size_of::<u32>()),
-            bytemuck::cast_slice(material_ids),
-        );
+    // Free a range returned by `insert` so that it can be reused.
+    pub fn remove(&self, range: Range<u32>) {
+        free_range(&self.allocator, range);
+    }
 
-        range
+    fn streams(&self) -> Vec<(&ArcSwap<wgpu::Buffer>, usize)> {
+        self.buffers
+            .buffers()
+            .into_iter()
+            .zip(L::STREAMS.iter().map(|&(_, size_of_field)| size_of_field))
+            .collect()
     }
 
-    fn resize(
-        allocator: &mut range_alloc::RangeAllocator<u32>,
-        buffers: &RawVertexBuffers<ArcSwap<wgpu::Buffer>>,
-        required_capacity: u32,
+    // Move all the live ranges to the start of new buffers, closing the holes left by `remove`.
+    pub fn compact(
+        &self,
         device: &wgpu::Device,
         command_encoder: &mut wgpu::CommandEncoder,
-    ) -> RawVertexBuffers<Arc<wgpu::Buffer>> {
-        let copy_range = allocator
-            .allocated_ranges()
-            .last()
-            .map(|range| range.end)
-            .unwrap_or(0);
+    ) -> Remapping {
+        compact_buffers(
+            &mut self.allocator.lock(),
+            &self.streams(),
+            command_encoder,
+            |capacity| Self::create_buffers(device, capacity),
+        )
+    }
 
-        let old_capacity = allocator.initial_range().end;
+    pub fn stats(&self) -> AllocatorStats {
+        AllocatorStats::new(&self.allocator.lock())
+    }
+}
 
-        let new_capacity = (old_capacity + required_capacity).max(old_capacity * 2);
+// Grow an allocator so that `required_capacity` more elements fit, moving the contents of each
+// (buffer, element size) stream into a new buffer from `create_buffers`. Returns the new buffers.
+fn resize_buffers(
+    allocator: &mut range_alloc::RangeAllocator<u32>,
+    streams: &[(&ArcSwap<wgpu::Buffer>, usize)],
+    label: &str,
+    required_capacity: u32,
+    command_encoder: &mut wgpu::CommandEncoder,
+    create_buffers: impl FnOnce(u32) -> Vec<Arc<wgpu::Buffer>>,
+) -> Vec<Arc<wgpu::Buffer>> {
+    let copy_range = allocator
+        .allocated_ranges()
+        .last()
+        .map(|range| range.end)
+        .unwrap_or(0);
 
-        log::info!(
-            "Growing vertex buffers from {} to {}",
-            old_capacity,
-            new_capacity
-        );
+    let old_capacity = allocator.initial_range().end;
 
-        allocator.grow_to(new_capacity);
+    let new_capacity = (old_capacity + required_capacity).max(old_capacity * 2);
 
-        let new_buffers = RawVertexBuffers {
-            position: create_buffer(device, "position buffer", new_capacity, size_of::<Vec3>()),
-            normal: create_buffer(device, "normal buffer", new_capacity, size_of::<Vec3>()),
-            uv: create_buffer(device, "uv buffer", new_capacity, size_of::<Vec2>()),
-            material_id: create_buffer(
-                device,
-                "material id buffer",
-                new_capacity,
-                size_of::<u32>(),
-            ),
-        };
+    log::info!(
+        "Growing {} from {} to {}",
+        label,
+        old_capacity,
+        new_capacity
+    );
 
-        let current_buffers = buffers.load();
+    allocator.grow_to(new_capacity);
 
+    let new_buffers = create_buffers(new_capacity);
+
+    for (&(buffer, size_of_field), new_buffer) in streams.iter().zip(&new_buffers) {
         command_encoder.copy_buffer_to_buffer(
-            &current_buffers.position,
-            0,
-            &new_buffers.position,
-            0,
-            size_in_bytes(copy_range, size_of::<Vec3>()),
-        );
-        command_encoder.copy_buffer_to_buffer(
-            &current_buffers.normal,
-            0,
-            &new_buffers.normal,
-            0,
-            size_in_bytes(copy_range, size_of::<Vec3>()),
-        );
-        command_encoder.copy_buffer_to_buffer(
-            &current_buffers.uv,
-            0,
-            &new_buffers.uv,
-            0,
-            size_in_bytes(copy_range, size_of::<Vec2>()),
-        );
-        command_encoder.copy_buffer_to_buffer(
-            &current_buffers.material_id,
+            &buffer.load(),
             0,
-            &new_buffers.material_id,
+            new_buffer,
             0,
-            size_in_bytes(copy_range, size_of::<u32>()),
+            size_in_bytes(copy_range, size_of_field),
         );
-        buffers.position.store(new_buffers.position.clone());
-        buffers.normal.store(new_buffers.normal.clone());
-        buffers.uv.store(new_buffers.uv.clone());
-        buffers.material_id.store(new_buffers.material_id.clone());
-
-        new_buffers
+        buffer.store(new_buffer.clone());
     }
-}
 
-pub struct RawAnimatedVertexBuffers<T> {
-    pub position: T,
-    pub normal: T,
-    pub uv: T,
-    pub joint_indices: T,
-    pub joint_weights: T,
+    new_buffers
 }
 
-impl RawAnimatedVertexBuffers<ArcSwap<wgpu::Buffer>> {
-    pub fn load(&self) -> RawAnimatedVertexBuffers<arc_swap::Guard<Arc<wgpu::Buffer>>> {
-        RawAnimatedVertexBuffers {
-            position: self.position.load(),
-            normal: self.normal.load(),
-            uv: self.uv.load(),
-            joint_indices: self.joint_indices.load(),
-            joint_weights: self.joint_weights.load(),
-        }
+// Pack the live ranges of an allocator together, copying each stream into a new buffer from
+// `create_buffers` to match.
+fn compact_buffers(
+    allocator: &mut range_alloc::RangeAllocator<u32>,
+    streams: &[(&ArcSwap<wgpu::Buffer>, usize)],
+    command_encoder: &mut wgpu::CommandEncoder,
+    create_buffers: impl FnOnce(u32) -> Vec<Arc<wgpu::Buffer>>,
+) -> Remapping {
+    let capacity = allocator.initial_range().end;
+    let remapping = compact_allocator(allocator);
+
+    for (&(buffer, size_of_field), new_buffer) in streams.iter().zip(create_buffers(capacity)) {
+        copy_ranges(
+            command_encoder,
+            &buffer.load(),
+            &new_buffer,
+            &remapping,
+            size_of_field,
+        );
+        buffer.store(new_buffer);
     }
-}
 
-fn size_in_bytes(size: u32, size_of_field: usize) -> u64 {
-    size as u64 * size_of_field as u64
+    remapping
 }
 
-fn create_buffer(
-    device: &wgpu::Device,
-    label: &str,
-    capacity: u32,
-    size_of_field: usize,
-) -> Arc<wgpu::Buffer> {
-    Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
-        label: Some(label),
-        size: size_in_bytes(capacity, size_of_field),
-        usage: wgpu::BufferUsages::VERTEX
-            | wgpu::BufferUsages::COPY_DST
-            | wgpu::BufferUsages::COPY_SRC,
-        mapped_at_creation: false,
-    }))
+// Where each block of live ranges in a buffer was moved to by `compact`.
+#[derive(Debug, Default)]
+pub struct Remapping {
+    // (old range, new start), sorted by the old range.
+    moves: Vec<(Range<u32>, u32)>,
 }
 
-pub struct AnimatedVertexBuffers {
-    allocator: parking_lot::Mutex<range_alloc::RangeAllocator<u32>>,
-    pub buffers: RawAnimatedVertexBuffers<ArcSwap<wgpu::Buffer>>,
-}
+impl Remapping {
+    // The new position of an element. Elements that weren't allocated are left as they are.
+    pub fn offset(&self, offset: u32) -> u32 {
+        let index = self.moves.partition_point(|(old, _)| old.end <= offset);
 
-impl AnimatedVertexBuffers {
-    pub fn new(capacity: u32, device: &wgpu::Device) -> Self {
-        Self {
-            allocator: parking_lot::Mutex::new(range_alloc::RangeAllocator::new(0..capacity)),
-            buffers: RawAnimatedVertexBuffers {
-                position: ArcSwap::from(create_buffer(
-                    device,
-                    "position buffer",
-                    capacity,
-                    size_of::<Vec3>(),
-                )),
-                normal: ArcSwap::from(create_buffer(
-                    device,
-                    "normal buffer",
-                    capacity,
-                    size_of::<Vec3>(),
-                )),
-                uv: ArcSwap::from(create_buffer(
-                    device,
-                    "uv buffer",
-                    capacity,
-                    size_of::<Vec2>(),
-                )),
-                joint_indices: ArcSwap::from(create_buffer(
-                    device,
-                    "joint indices buffer",
-                    capacity,
-                    size_of::<UVec4>(),
-                )),
-                joint_weights: ArcSwap::from(create_buffer(
-                    device,
-                    "joint weights buffer",
-                    capacity,
-                    size_of::<Vec4>(),
-                )),
-            },
+        match self.moves.get(index) {
+            Some((old, new_start)) if old.contains(&offset) => new_start + (offset - old.start),
+            _ => offset,
         }
     }
 
-    #[allow(clippy::too_many_arguments)]
-    pub fn insert(
-        &self,
-        positions: &[Vec3],
-        normals: &[Vec3],
-        uvs: &[Vec2],
-        joint_indices: &[UVec4],
-        joint_weights: &[Vec4],
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        command_encoder: &mut wgpu::CommandEncoder,
-    ) -> Range<u32> {
-        let length = positions.len() as u32;
-
-        debug_assert_eq!(positions.len(), normals.len());
-        debug_assert_eq!(positions.len(), uvs.len());
-        debug_assert_eq!(positions.len(), joint_indices.len());
-        debug_assert_eq!(positions.len(), joint_weights.len());
+    pub fn range(&self, range: Range<u32>) -> Range<u32> {
+        if range.is_empty() {
+            return range;
+        }
 
-        let (buffers, range) = {
-            let mut allocator = self.allocator.lock();
+        let start = self.offset(range.start);
+        start..start + range.len() as u32
+    }
+}
 
-            match allocator.allocate_range(length) {
-                Ok(range) => {
-                    let buffers = RawAnimatedVertexBuffers {
-                        position: self.buffers.position.load_full(),
-                        normal: self.buffers.normal.load_full(),
-                        uv: self.buffers.uv.load_full(),
-                        joint_indices: self.buffers.joint_indices.load_full(),
-                        joint_weights: self.buffers.joint_weights.load_full(),
-                    };
+// Pack the allocated ranges of an allocator together at the start, returning where they moved to.
+fn compact_allocator(allocator: &mut range_alloc::RangeAllocator<u32>) -> Remapping {
+    let mut moves = Vec::new();
+    let mut end = 0;
 
-                    (buffers, range)
-                }
-                Err(_) => {
-                    let new_buffers = Self::resize(
-                        &mut allocator,
-                        &self.buffers,
-                        length,
-                        device,
-                        command_encoder,
-                    );
-                    let range = allocator.allocate_range(length).expect("just resized");
-                    (new_buffers, range)
-                }
-            }
-        };
+    for range in allocator.allocated_ranges() {
+        let length = range.end - range.start;
+        moves.push((range, end));
+        end += length;
+    }
 
-        queue.write_buffer(
-            &buffers.position,
-            size_in_bytes(range.start, size_of::<Vec3>()),
-            bytemuck::cast_slice(positions),
-        );
+    allocator.reset();
 
-        queue.write_buffer(
-            &buffers.normal,
-            size_in_bytes(range.start, size_of::<Vec3>()),
-            bytemuck::cast_slice(normals),
-        );
+    if end > 0 {
+        allocator
+            .allocate_range(end)
+            .expect("the live ranges fit before");
+    }
 
-        queue.write_buffer(
-            &buffers.uv,
-            size_in_bytes(range.start, size_of::<Vec2>()),
-            bytemuck::cast_slice(uvs),
-        );
+    Remapping { moves }
+}
 
-        queue.write_buffer(
-            &buffers.joint_indices,
-            size_in_bytes(range.start, size_of::<UVec4>()),
-            bytemuck::cast_slice(joint_indices),
+fn copy_ranges(
+    command_encoder: &mut wgpu::CommandEncoder,
+    source: &wgpu::Buffer,
+    dest: &wgpu::Buffer,
+    remapping: &Remapping,
+    size_of_field: usize,
+) {
+    for (old, new_start) in &remapping.moves {
+        command_encoder.copy_buffer_to_buffer(
+            source,
+            size_in_bytes(old.start, size_of_field),
+            dest,
+            size_in_bytes(*new_start, size_of_field),
+            size_in_bytes(old.end - old.start, size_of_field),
         );
+    }
+}
 
-        queue.write_buffer(
-            &buffers.joint_weights,
-            size_in_bytes(range.start, size_of::<Vec4>()),
-            bytemuck::cast_slice(joint_weights),
-        );
+// How much of a range-allocated buffer is in use, in elements.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AllocatorStats {
+    pub capacity: u32,
+    pub allocated: u32,
+    // The number of holes between (and after) allocated ranges.
+    pub free_ranges: u32,
+    pub largest_free_range: u32,
+}
 
-        range
-    }
+impl AllocatorStats {
+    fn new(allocator: &range_alloc::RangeAllocator<u32>) -> Self {
+        let capacity = allocator.initial_range().end;
+        let mut stats = Self {
+            capacity,
+            ..Default::default()
+        };
+        let mut end = 0;
 
-    fn resize(
-        allocator: &mut range_alloc::RangeAllocator<u32>,
-        buffers: &RawAnimatedVertexBuffers<ArcSwap<wgpu::Buffer>>,
-        required_capacity: u32,
-        device: &wgpu::Device,
-        command_encoder: &mut wgpu::CommandEncoder,
-    ) -> RawAnimatedVertexBuffers<Arc<wgpu::Buffer>> {
-        let copy_range = allocator
+        for range in allocator
             .allocated_ranges()
-            .last()
-            .map(|range| range.end)
-            .unwrap_or(0);
-
-        let old_capacity = allocator.initial_range().end;
+            .chain(std::iter::once(capacity..capacity))
+        {
+            if range.start > end {
+                stats.free_ranges += 1;
+                stats.largest_free_range = stats.largest_free_range.max(range.start - end);
+            }
 
-        let new_capacity = (old_capacity + required_capacity).max(old_capacity * 2);
+            stats.allocated += range.end - range.start;
+            end = range.end;
+        }
 
-        log::info!(
-            "Growing animated vertex buffers from {} to {}",
-            old_capacity,
-            new_capacity
-        );
+        stats
+    }
+}
 
-        allocator.grow_to(new_capacity);
+impl std::fmt::Display for AllocatorStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{} allocated, {} free ranges (largest {})",
+            self.allocated, self.capacity, self.free_ranges, self.largest_free_range
+        )
+    }
+}
 
-        let new_buffers = RawAnimatedVertexBuffers {
-            position: create_buffer(device, "position buffer", new_capacity, size_of::<Vec3>()),
-            normal: create_buffer(device, "normal buffer", new_capacity, size_of::<Vec3>()),
-            uv: create_buffer(device, "uv buffer", new_capacity, size_of::<Vec2>()),
-            joint_indices: create_buffer(
-                device,
-                "joint indices buffer",
-                new_capacity,
-                size_of::<UVec4>(),
-            ),
-            joint_weights: create_buffer(
-                device,
-                "joint weights buffer",
-                new_capacity,
-                size_of::<Vec4>(),
-            ),
-        };
+fn free_range(allocator: &parking_lot::Mutex<range_alloc::RangeAllocator<u32>>, range: Range<u32>) {
+    // Empty ranges are never allocated in the first place.
+    if !range.is_empty() {
+        allocator.lock().free_range(range);
+    }
+}
 
-        let current_buffers = buffers.load();
+fn size_in_bytes(size: u32, size_of_field: usize) -> u64 {
+    size as u64 * size_of_field as u64
+}
 
-        command_encoder.copy_buffer_to_buffer(
-            &current_buffers.position,
-            0,
-            &new_buffers.position,
-            0,
-            size_in_bytes(copy_range, size_of::<Vec3>()),
-        );
-        command_encoder.copy_buffer_to_buffer(
-            &current_buffers.normal,
-            0,
-            &new_buffers.normal,
-            0,
-            size_in_bytes(copy_range, size_of::<Vec3>()),
-        );
-        command_encoder.copy_buffer_to_buffer(
-            &current_buffers.uv,
-            0,
-            &new_buffers.uv,
-            0,
-            size_in_bytes(copy_range, size_of::<Vec2>()),
-        );
-        command_encoder.copy_buffer_to_buffer(
-            &current_buffers.joint_indices,
-            0,
-            &new_buffers.joint_indices,
-            0,
-            size_in_bytes(copy_range, size_of::<UVec4>()),
-        );
-        command_encoder.copy_buffer_to_buffer(
-            &current_buffers.joint_weights,
-            0,
-            &new_buffers.joint_weights,
-            0,
-            size_in_bytes(copy_range, size_of::<Vec4>()),
-        );
+fn create_buffer(
+    device: &wgpu::Device,
+    label: &str,
+    capacity: u32,
+    size_of_field: usize,
+    usage: wgpu::BufferUsages,
+) -> Arc<wgpu::Buffer> {
+    Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: size_in_bytes(capacity, size_of_field),
+        usage: usage | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    }))
+}
 
-        buffers.position.store(new_buffers.position.clone());
-        buffers.normal.store(new_buffers.normal.clone());
-        buffers.uv.store(new_buffers.uv.clone());
-        buffers
-            .joint_indices
-            .store(new_buffers.joint_indices.clone());
-        buffers
-            .joint_weights
-            .store(new_buffers.joint_weights.clone());
-
-        new_buffers
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compaction_closes_holes() {
+        let mut allocator = range_alloc::RangeAllocator::new(0..100);
+        let a = allocator.allocate_range(10).unwrap();
+        let b = allocator.allocate_range(20).unwrap();
+        let c = allocator.allocate_range(5).unwrap();
+        let d = allocator.allocate_range(15).unwrap();
+        allocator.free_range(a);
+        allocator.free_range(c);
+
+        let stats = AllocatorStats::new(&allocator);
+        assert_eq!(stats.allocated, 35);
+        assert_eq!(stats.free_ranges, 3);
+        assert_eq!(stats.largest_free_range, 50);
+
+        let remapping = compact_allocator(&mut allocator);
+        assert_eq!(remapping.range(b), 0..20);
+        assert_eq!(remapping.range(d.clone()), 20..35);
+        assert_eq!(remapping.offset(d.start + 3), 23);
+        assert_eq!(remapping.range(0..0), 0..0);
+
+        let stats = AllocatorStats::new(&allocator);
+        assert_eq!(stats.allocated, 35);
+        assert_eq!(stats.free_ranges, 1);
+        assert_eq!(allocator.allocate_range(65), Ok(35..100));
     }
 }
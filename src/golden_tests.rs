@@ -0,0 +1,161 @@
+// Render the entry points in `shaders/pipeline/tests.rpsl` on a software adapter and compare the
+// results to the reference images in `tests/golden`.
+//
+// `./golden_tests.sh` builds the module and runs these tests. Set `BLESS_GOLDEN_IMAGES=1` to write
+// new reference images instead of comparing against them.
+
+use crate::headless::{OffscreenTarget, FORMAT};
+use crate::scene::Scene;
+use crate::{load_entry_point, request_device, Renderer, UserData};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 256;
+// The maximum difference allowed in any channel of a pixel.
+const TOLERANCE: u8 = 3;
+
+const MODULE: &str = "shaders/pipeline/tests.so";
+const SCENE: &str = "tests/golden/scene.ron";
+const REFERENCE_DIR: &str = "tests/golden";
+const OUTPUT_DIR: &str = "target/golden";
+
+// RPS and the software adapters aren't tested with several devices at once, so render one
+// test at a time.
+static RENDER_LOCK: Mutex<()> = Mutex::new(());
+
+fn render(entry_point: &str) -> image::RgbaImage {
+    let _guard = RENDER_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    let (_lib, entry) = load_entry_point(Path::new(MODULE), entry_point).unwrap();
+
+    let instance = wgpu::Instance::default();
+
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::HighPerformance,
+        force_fallback_adapter: true,
+        compatible_surface: None,
+    }))
+    .expect("Failed to find a fallback adapter");
+
+    let (device, queue) = request_device(&adapter).unwrap();
+
+    let device = Arc::new(device);
+
+    let target = OffscreenTarget::new(&device, WIDTH, HEIGHT);
+
+    let scene = Scene::load(Path::new(SCENE)).unwrap();
+
+    let mut user_data = UserData::new(device, queue, &scene, FORMAT, None, WIDTH, HEIGHT).unwrap();
+
+    user_data.finish_loading_textures();
+
+    // Replace the scene's camera with one that doesn't smooth its movement.
+    user_data.camera_rig = dolly::rig::CameraRig::builder()
+        .with(dolly::drivers::Position::new(scene.camera.position))
+        .with(
+            dolly::drivers::YawPitch::new()
+                .yaw_degrees(scene.camera.yaw)
+                .pitch_degrees(scene.camera.pitch),
+        )
+        .build();
+
+    unsafe {
+        let mut renderer = Renderer::new(entry, user_data, FORMAT);
+
+        renderer.render(target.view(), WIDTH, HEIGHT, FORMAT);
+
+        let user_data = renderer.user_data();
+        target.read_back(&user_data.device, &user_data.queue)
+    }
+}
+
+fn compare_to_reference(name: &str, image: &image::RgbaImage) {
+    let reference_path = Path::new(REFERENCE_DIR).join(format!("{}.png", name));
+
+    if std::env::var_os("BLESS_GOLDEN_IMAGES").is_some() {
+        std::fs::create_dir_all(REFERENCE_DIR).unwrap();
+        image.save(&reference_path).unwrap();
+        return;
+    }
+
+    let reference = match image::open(&reference_path) {
+        Ok(reference) => reference.to_rgba8(),
+        Err(error) => panic!(
+            "Failed to open {}: {}. Run with BLESS_GOLDEN_IMAGES=1 to create it.",
+            reference_path.display(),
+            error
+        ),
+    };
+
+    assert_eq!(reference.dimensions(), image.dimensions());
+
+    let mut diff = image::RgbaImage::new(image.width(), image.height());
+    let mut mismatched_pixels = 0;
+
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let reference_pixel = reference.get_pixel(x, y);
+
+        let max_difference = pixel
+            .0
+            .iter()
+            .zip(&reference_pixel.0)
+            .map(|(a, b)| a.abs_diff(*b))
+            .max()
+            .unwrap_or(0);
+
+        // Mismatches are red, everything else is a faded copy of the reference.
+        let diff_pixel = if max_difference > TOLERANCE {
+            mismatched_pixels += 1;
+            [255, 0, 0, 255]
+        } else {
+            let [r, g, b, _] = reference_pixel.0;
+            [r / 4, g / 4, b / 4, 255]
+        };
+
+        diff.put_pixel(x, y, image::Rgba(diff_pixel));
+    }
+
+    if mismatched_pixels > 0 {
+        std::fs::create_dir_all(OUTPUT_DIR).unwrap();
+
+        let actual_path = Path::new(OUTPUT_DIR).join(format!("{}.png", name));
+        let diff_path = Path::new(OUTPUT_DIR).join(format!("{}.diff.png", name));
+
+        image.save(&actual_path).unwrap();
+        diff.save(&diff_path).unwrap();
+
+        panic!(
+            "{} pixels differ from {} by more than {}. See {} and {}.",
+            mismatched_pixels,
+            reference_path.display(),
+            TOLERANCE,
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+fn run_golden_test(entry_point: &str) {
+    compare_to_reference(entry_point, &render(entry_point));
+}
+
+#[test]
+#[ignore = "requires a fallback adapter and a compiled shaders/pipeline/tests.so"]
+fn skybox() {
+    run_golden_test("skybox");
+}
+
+#[test]
+#[ignore = "requires a fallback adapter and a compiled shaders/pipeline/tests.so"]
+fn tonemap_scene() {
+    run_golden_test("tonemap_scene");
+}
+
+#[test]
+#[ignore = "requires a fallback adapter and a compiled shaders/pipeline/tests.so"]
+fn bloom() {
+    run_golden_test("bloom");
+}
@@ -0,0 +1,209 @@
+use crate::scene::Scene;
+use crate::{request_device, Opts, Renderer, UserData};
+use rps_custom_backend::rps;
+use std::path::Path;
+use std::sync::Arc;
+
+pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+#[derive(Clone, Copy)]
+pub enum OutputFormat {
+    Png,
+    Exr,
+}
+
+impl OutputFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Exr => "exr",
+        }
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(string: &str) -> anyhow::Result<Self> {
+        match string {
+            "png" => Ok(Self::Png),
+            "exr" => Ok(Self::Exr),
+            other => Err(anyhow::anyhow!("Unsupported output format: {}", other)),
+        }
+    }
+}
+
+// A texture to render into in place of a swapchain frame, plus a buffer to read it back with.
+pub struct OffscreenTarget {
+    texture: wgpu::Texture,
+    readback_buffer: wgpu::Buffer,
+    padded_bytes_per_row: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl OffscreenTarget {
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let unpadded_bytes_per_row = width * 4;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = ((unpadded_bytes_per_row + align - 1) / align) * align;
+
+        Self {
+            texture: device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("offscreen target"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            }),
+            readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("offscreen readback buffer"),
+                size: padded_bytes_per_row as u64 * height as u64,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }),
+            padded_bytes_per_row,
+            width,
+            height,
+        }
+    }
+
+    pub fn view(&self) -> wgpu::TextureView {
+        self.texture.create_view(&Default::default())
+    }
+
+    // Copy the texture to the CPU, blocking until the GPU is finished.
+    pub fn read_back(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> image::RgbaImage {
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &self.readback_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        queue.submit(Some(encoder.finish()));
+
+        let slice = self.readback_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+        device.poll(wgpu::Maintain::Wait);
+
+        let mut pixels = Vec::with_capacity((self.width * self.height * 4) as usize);
+
+        {
+            let mapped = slice.get_mapped_range();
+
+            for row in mapped.chunks(self.padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..self.width as usize * 4]);
+            }
+        }
+
+        self.readback_buffer.unmap();
+
+        image::RgbaImage::from_raw(self.width, self.height, pixels).unwrap()
+    }
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn save_frame(
+    image: image::RgbaImage,
+    path: &Path,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Png => image.save(path)?,
+        OutputFormat::Exr => {
+            // EXRs are expected to be linear, while the frame is sRGB-encoded.
+            let mut image = image::DynamicImage::ImageRgba8(image).into_rgba32f();
+
+            for pixel in image.pixels_mut() {
+                for channel in &mut pixel.0[..3] {
+                    *channel = srgb_to_linear(*channel);
+                }
+            }
+
+            image.save(path)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub unsafe fn run(entry: rps::RpslEntry, scene: &Scene, opts: &Opts) -> anyhow::Result<()> {
+    let instance = wgpu::Instance::default();
+
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::HighPerformance,
+        force_fallback_adapter: opts.force_fallback_adapter,
+        compatible_surface: None,
+    }))
+    .ok_or_else(|| anyhow::anyhow!("Failed to find a suitable adapter"))?;
+
+    let (device, queue) = request_device(&adapter)?;
+
+    let device = Arc::new(device);
+
+    let target = OffscreenTarget::new(&device, opts.width, opts.height);
+
+    let mut user_data = UserData::new(device, queue, scene, FORMAT, None, opts.width, opts.height)?;
+
+    user_data.finish_loading_textures();
+
+    let mut renderer = Renderer::new(entry, user_data, FORMAT);
+
+    std::fs::create_dir_all(&opts.output)?;
+
+    for frame in 0..opts.frames {
+        let delta_time = 1.0 / 60.0;
+        renderer.user_data().camera_rig.update(delta_time);
+        renderer.user_data().update(delta_time);
+
+        renderer.render(target.view(), target.width, target.height, FORMAT);
+
+        let user_data = renderer.user_data();
+        let image = target.read_back(&user_data.device, &user_data.queue);
+
+        let path = opts
+            .output
+            .join(format!("frame_{:05}.{}", frame, opts.format.extension()));
+
+        save_frame(image, &path, opts.format)?;
+
+        log::info!("Wrote {}", path.display());
+    }
+
+    Ok(())
+}
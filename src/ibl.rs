@@ -0,0 +1,105 @@
+use crate::texture_loading::mip_level_count;
+
+const SPECULAR_SIZE: u32 = 128;
+const IRRADIANCE_SIZE: u32 = 32;
+const BRDF_LUT_SIZE: u32 = 256;
+
+// Image-based lighting textures derived from the skybox cubemap. They're filled in by the
+// `prefilter_specular`, `compute_irradiance` and `integrate_brdf` nodes the first time those run.
+pub struct ImageBasedLighting {
+    // GGX prefiltered radiance, with roughness increasing linearly with each mip.
+    pub specular: wgpu::Texture,
+    // Cosine-weighted irradiance, divided by pi.
+    pub irradiance: wgpu::Texture,
+    // The scale and bias applied to F0 in the split-sum approximation, indexed by n dot v and
+    // roughness.
+    pub brdf_lut: wgpu::Texture,
+    // Clamped and trilinear, so that the specular roughness can be interpolated between mips.
+    pub sampler: wgpu::Sampler,
+    pub prefiltered_specular: bool,
+    pub computed_irradiance: bool,
+    pub integrated_brdf: bool,
+}
+
+impl ImageBasedLighting {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let create_cubemap = |size: u32, mip_level_count: u32, label: &str| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: 6,
+                },
+                mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba16Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+                view_formats: &[],
+            })
+        };
+
+        Self {
+            // Stop at 8x8 so that the roughest mip still has some directional detail.
+            specular: create_cubemap(
+                SPECULAR_SIZE,
+                mip_level_count(SPECULAR_SIZE, SPECULAR_SIZE) - 3,
+                "ibl specular",
+            ),
+            irradiance: create_cubemap(IRRADIANCE_SIZE, 1, "ibl irradiance"),
+            brdf_lut: device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("ibl brdf lut"),
+                size: wgpu::Extent3d {
+                    width: BRDF_LUT_SIZE,
+                    height: BRDF_LUT_SIZE,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rg16Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+                view_formats: &[],
+            }),
+            sampler: device.create_sampler(&wgpu::SamplerDescriptor {
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                mipmap_filter: wgpu::FilterMode::Linear,
+                ..Default::default()
+            }),
+            prefiltered_specular: false,
+            computed_irradiance: false,
+            integrated_brdf: false,
+        }
+    }
+
+    pub fn specular_view(&self) -> wgpu::TextureView {
+        cube_view(&self.specular)
+    }
+
+    pub fn irradiance_view(&self) -> wgpu::TextureView {
+        cube_view(&self.irradiance)
+    }
+
+    pub fn brdf_lut_view(&self) -> wgpu::TextureView {
+        self.brdf_lut.create_view(&Default::default())
+    }
+}
+
+fn cube_view(texture: &wgpu::Texture) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::Cube),
+        ..Default::default()
+    })
+}
+
+// A view of every face of a single mip of a cubemap, for writing to as a storage texture.
+pub fn storage_view(texture: &wgpu::Texture, mip: u32) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        base_mip_level: mip,
+        mip_level_count: Some(1),
+        ..Default::default()
+    })
+}
@@ -0,0 +1,215 @@
+use crate::buffers::VecGpuBuffer;
+use crate::model_loading::Model;
+use glam::{Mat4, Quat, Vec3};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+#[derive(Clone, Copy)]
+pub struct Instance {
+    pub position: Vec3,
+    pub scale: f32,
+    pub rotation: Quat,
+}
+
+impl Instance {
+    fn as_matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(Vec3::splat(self.scale), self.rotation, self.position)
+    }
+}
+
+// A single indexed draw of one of a model's meshes.
+pub struct Draw {
+    pub indices: Range<u32>,
+    // Added to each index, as indices are relative to the start of the model's vertices.
+    pub base_vertex: i32,
+    pub instances: Range<u32>,
+}
+
+// An index into `UserData::models`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct ModelHandle(pub usize);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct InstanceHandle {
+    index: u32,
+    generation: u32,
+}
+
+#[derive(Default)]
+struct Slot {
+    generation: u32,
+    value: Option<(ModelHandle, Instance)>,
+}
+
+pub struct Instances {
+    slots: Vec<Slot>,
+    free_slots: Vec<u32>,
+    // Holds a transform for every (instance, mesh node) pair.
+    buffer: VecGpuBuffer<Mat4>,
+    draws: Vec<Draw>,
+    dirty: bool,
+}
+
+impl Instances {
+    pub fn new(capacity: u32, device: &wgpu::Device) -> Self {
+        Self {
+            slots: Vec::new(),
+            free_slots: Vec::new(),
+            buffer: VecGpuBuffer::new(
+                capacity,
+                device,
+                wgpu::BufferUsages::VERTEX,
+                "instance buffer",
+            ),
+            draws: Vec::new(),
+            dirty: false,
+        }
+    }
+
+    pub fn insert(&mut self, model: ModelHandle, instance: Instance) -> InstanceHandle {
+        self.dirty = true;
+
+        let index = match self.free_slots.pop() {
+            Some(index) => index,
+            None => {
+                self.slots.push(Slot::default());
+                self.slots.len() as u32 - 1
+            }
+        };
+
+        let slot = &mut self.slots[index as usize];
+        slot.value = Some((model, instance));
+
+        InstanceHandle {
+            index,
+            generation: slot.generation,
+        }
+    }
+
+    pub fn remove(&mut self, handle: InstanceHandle) -> Option<Instance> {
+        let slot = self.slot_mut(handle)?;
+        let (_, instance) = slot.value.take()?;
+        slot.generation += 1;
+
+        self.free_slots.push(handle.index);
+        self.dirty = true;
+
+        Some(instance)
+    }
+
+    // Remove every instance of a model.
+    pub fn remove_model(&mut self, model: ModelHandle) {
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            if matches!(slot.value, Some((instance_model, _)) if instance_model == model) {
+                slot.value = None;
+                slot.generation += 1;
+                self.free_slots.push(index as u32);
+                self.dirty = true;
+            }
+        }
+    }
+
+    pub fn get(&self, handle: InstanceHandle) -> Option<&Instance> {
+        self.slots
+            .get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.value.as_ref())
+            .map(|(_, instance)| instance)
+    }
+
+    // The model and transform of every live instance.
+    pub fn iter(&self) -> impl Iterator<Item = (ModelHandle, Mat4)> + '_ {
+        self.slots
+            .iter()
+            .filter_map(|slot| slot.value.as_ref())
+            .map(|(model, instance)| (*model, instance.as_matrix()))
+    }
+
+    pub fn get_mut(&mut self, handle: InstanceHandle) -> Option<&mut Instance> {
+        let slot = self
+            .slots
+            .get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation && slot.value.is_some())?;
+
+        self.dirty = true;
+
+        slot.value.as_mut().map(|(_, instance)| instance)
+    }
+
+    fn slot_mut(&mut self, handle: InstanceHandle) -> Option<&mut Slot> {
+        self.slots
+            .get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+    }
+
+    // Re-upload the transforms of all the live instances if any of them have changed. Each mesh
+    // node of a model gets its own draw, with the instances of the model grouped together.
+    pub fn upload(
+        &mut self,
+        models: &[Option<Model>],
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        command_encoder: &mut wgpu::CommandEncoder,
+    ) {
+        if !self.dirty {
+            return;
+        }
+
+        let mut instances_by_model: BTreeMap<ModelHandle, Vec<Mat4>> = BTreeMap::new();
+
+        for (model, instance) in self.slots.iter().filter_map(|slot| slot.value.as_ref()) {
+            instances_by_model
+                .entry(*model)
+                .or_default()
+                .push(instance.as_matrix());
+        }
+
+        self.draws.clear();
+
+        let mut transforms = Vec::new();
+
+        for (model, instance_transforms) in &instances_by_model {
+            // Handles to models that haven't finished loading or were never loaded are skipped.
+            let model = match models.get(model.0).and_then(Option::as_ref) {
+                Some(model) => model,
+                None => continue,
+            };
+
+            for &(mesh, node_transform) in &model.mesh_instances {
+                let start = transforms.len() as u32;
+
+                transforms.extend(
+                    instance_transforms
+                        .iter()
+                        .map(|&instance_transform| instance_transform * node_transform),
+                );
+
+                self.draws.push(Draw {
+                    indices: model.meshes[mesh].clone(),
+                    base_vertex: model.vertices.start as i32,
+                    instances: start..transforms.len() as u32,
+                });
+            }
+        }
+
+        // Nothing needs to be preserved, so clearing first means a resize doesn't have to copy.
+        self.buffer.clear();
+        self.buffer
+            .push(&transforms, device, queue, command_encoder);
+
+        self.dirty = false;
+    }
+
+    // Rebuild the draws on the next upload, e.g. because model ranges have moved.
+    pub fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn draws(&self) -> &[Draw] {
+        &self.draws
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer.buffer
+    }
+}
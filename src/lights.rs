@@ -0,0 +1,240 @@
+use crate::buffers::VecGpuBuffer;
+use crate::instances::Instances;
+use crate::model_loading::Model;
+use glam::{Mat4, UVec3, Vec2, Vec3};
+
+// A `KHR_lights_punctual` light, laid out to match `Light` in `moon.hlsl`.
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+pub struct Light {
+    pub position: Vec3,
+    // The distance at which the light has no more effect, or 0 for an infinite range.
+    pub range: f32,
+    // The direction that directional and spot lights point in.
+    pub direction: Vec3,
+    pub ty: u32,
+    pub color: Vec3,
+    // Lux for directional lights and candela for point and spot lights.
+    pub intensity: f32,
+    pub inner_cone_cos: f32,
+    pub outer_cone_cos: f32,
+    pub _padding: [u32; 2],
+}
+
+impl Light {
+    pub const DIRECTIONAL: u32 = 0;
+    pub const POINT: u32 = 1;
+    pub const SPOT: u32 = 2;
+
+    pub fn directional(direction: Vec3, color: Vec3, intensity: f32) -> Self {
+        Self {
+            position: Vec3::ZERO,
+            range: 0.0,
+            direction: direction.normalize(),
+            ty: Self::DIRECTIONAL,
+            color,
+            intensity,
+            inner_cone_cos: 0.0,
+            outer_cone_cos: 0.0,
+            _padding: [0; 2],
+        }
+    }
+
+    pub fn point(position: Vec3, color: Vec3, intensity: f32, range: Option<f32>) -> Self {
+        Self {
+            position,
+            range: range.unwrap_or(0.0),
+            direction: Vec3::NEG_Z,
+            ty: Self::POINT,
+            color,
+            intensity,
+            inner_cone_cos: 0.0,
+            outer_cone_cos: 0.0,
+            _padding: [0; 2],
+        }
+    }
+
+    // The cone angles are in radians, from the center of the cone to its edge.
+    #[allow(clippy::too_many_arguments)]
+    pub fn spot(
+        position: Vec3,
+        direction: Vec3,
+        color: Vec3,
+        intensity: f32,
+        range: Option<f32>,
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    ) -> Self {
+        Self {
+            direction: direction.normalize(),
+            ty: Self::SPOT,
+            inner_cone_cos: inner_cone_angle.cos(),
+            outer_cone_cos: outer_cone_angle.cos(),
+            ..Self::point(position, color, intensity, range)
+        }
+    }
+
+    pub fn transformed(&self, transform: Mat4) -> Self {
+        Self {
+            position: transform.transform_point3(self.position),
+            direction: transform.transform_vector3(self.direction).normalize(),
+            ..*self
+        }
+    }
+}
+
+// Matches `MAX_LIGHTS_PER_CLUSTER` in `lights.hlsl`. Any more lights than this are dropped.
+pub const MAX_LIGHTS_PER_CLUSTER: u32 = 128;
+
+// The range over which cluster depth slices are spaced exponentially.
+pub const CLUSTER_Z_NEAR: f32 = 0.1;
+pub const CLUSTER_Z_FAR: f32 = 1000.0;
+
+// Laid out to match `ClusterUniforms` in `lights.hlsl`.
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+pub struct ClusterUniforms {
+    pub view: Mat4,
+    pub projection_scale: Vec2,
+    pub projection_near: f32,
+    pub light_count: u32,
+    pub dimensions: UVec3,
+    pub z_near: f32,
+    pub tile_size: Vec2,
+    pub z_far: f32,
+    pub _padding: u32,
+}
+
+// The light lists of a grid of view space clusters, written by `cull_lights` and read by `draw`.
+pub struct Clusters {
+    pub dimensions: UVec3,
+    pub uniforms: wgpu::Buffer,
+    // The number of lights in each cluster.
+    pub light_counts: wgpu::Buffer,
+    // `MAX_LIGHTS_PER_CLUSTER` slots of light indices for each cluster.
+    pub light_indices: wgpu::Buffer,
+}
+
+impl Clusters {
+    fn new(dimensions: UVec3, device: &wgpu::Device) -> Self {
+        let count = (dimensions.x * dimensions.y * dimensions.z) as u64;
+
+        let create_storage_buffer = |size: u64, label: &str| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: size * std::mem::size_of::<u32>() as u64,
+                usage: wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            })
+        };
+
+        Self {
+            dimensions,
+            uniforms: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("cluster uniforms"),
+                size: std::mem::size_of::<ClusterUniforms>() as u64,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            light_counts: create_storage_buffer(count, "cluster light counts"),
+            light_indices: create_storage_buffer(
+                count * MAX_LIGHTS_PER_CLUSTER as u64,
+                "cluster light indices",
+            ),
+        }
+    }
+}
+
+// The lights from the scene and the UI, along with the lights of every model instance, uploaded
+// each frame.
+pub struct Lights {
+    // World space lights that aren't attached to a model.
+    pub lights: Vec<Light>,
+    buffer: VecGpuBuffer<Light>,
+    clusters: Option<Clusters>,
+}
+
+impl Lights {
+    pub fn new(lights: Vec<Light>, device: &wgpu::Device) -> Self {
+        Self {
+            lights,
+            buffer: VecGpuBuffer::new(64, device, wgpu::BufferUsages::STORAGE, "light buffer"),
+            clusters: None,
+        }
+    }
+
+    // Write every light to the buffer, returning the number of lights.
+    pub fn upload(
+        &mut self,
+        models: &[Option<Model>],
+        instances: &Instances,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        command_encoder: &mut wgpu::CommandEncoder,
+    ) -> u32 {
+        let mut lights: Vec<Light> = self.all_lights(models, instances).collect();
+
+        let count = lights.len() as u32;
+
+        // Storage buffer bindings can't be empty, so pad with an unlit light.
+        if lights.is_empty() {
+            lights.push(Light::point(Vec3::ZERO, Vec3::ZERO, 0.0, None));
+        }
+
+        // Cleared first so that growing the buffer doesn't copy the previous frame's lights.
+        self.buffer.clear();
+        self.buffer.push(&lights, device, queue, command_encoder);
+
+        count
+    }
+
+    // Every light in the order that they're uploaded in, with the world space lights first.
+    fn all_lights<'a>(
+        &'a self,
+        models: &'a [Option<Model>],
+        instances: &'a Instances,
+    ) -> impl Iterator<Item = Light> + 'a {
+        let model_lights = instances.iter().flat_map(move |(model, transform)| {
+            models
+                .get(model.0)
+                .and_then(Option::as_ref)
+                .into_iter()
+                .flat_map(move |model| {
+                    model
+                        .lights
+                        .iter()
+                        .map(move |light| light.transformed(transform))
+                })
+        });
+
+        self.lights.iter().copied().chain(model_lights)
+    }
+
+    // The first directional light and its index in the light buffer.
+    pub fn first_directional(
+        &self,
+        models: &[Option<Model>],
+        instances: &Instances,
+    ) -> Option<(u32, Light)> {
+        self.all_lights(models, instances)
+            .enumerate()
+            .find(|(_, light)| light.ty == Light::DIRECTIONAL)
+            .map(|(index, light)| (index as u32, light))
+    }
+
+    pub fn buffer(&self) -> &VecGpuBuffer<Light> {
+        &self.buffer
+    }
+
+    // (Re)create the cluster buffers if the grid dimensions have changed.
+    pub fn resize_clusters(&mut self, dimensions: UVec3, device: &wgpu::Device) {
+        if self.clusters.as_ref().map(|clusters| clusters.dimensions) != Some(dimensions) {
+            self.clusters = Some(Clusters::new(dimensions, device));
+        }
+    }
+
+    // `None` until `resize_clusters` has been called by `cull_lights`.
+    pub fn clusters(&self) -> Option<&Clusters> {
+        self.clusters.as_ref()
+    }
+}
@@ -3,7 +3,7 @@ use winit::event::*;
 
 use std::collections::HashSet;
 use std::ffi::c_void;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Instant;
 use structopt::StructOpt;
@@ -11,14 +11,25 @@ use wgpu::util::DeviceExt;
 use winit::window::Window;
 
 mod accessors;
+mod animation;
 mod bindless_textures;
 mod buffers;
 mod builtin_callbacks;
+#[cfg(test)]
+mod golden_tests;
+mod headless;
+mod ibl;
+mod instances;
+mod lights;
+mod mip_generation;
 mod model_loading;
 mod node_callbacks;
 mod pipelines;
 mod reflection;
+mod scene;
+mod shadows;
 mod texture_loading;
+mod texture_streaming;
 
 use std::ops::Range;
 
@@ -38,206 +49,206 @@ fn box_untyped<T>(ty: T) -> *mut c_void {
 }
 
 struct UserData {
-    rotation: f32,
     device: Arc<wgpu::Device>,
     sampler: wgpu::Sampler,
     repeat_sampler: wgpu::Sampler,
     camera_rig: dolly::rig::CameraRig,
-    moon: Model,
-    bloom: Model,
+    // `None` for models that have been unloaded, so that `ModelHandle`s stay valid.
+    models: Vec<Option<Model>>,
+    instances: instances::Instances,
+    // Instances that rotate around the Y axis, in radians per second.
+    spinning_instances: Vec<(instances::InstanceHandle, f32)>,
+    lights: lights::Lights,
     index_buffer: buffers::IndexBuffer,
     vertex_buffers: buffers::VertexBuffers,
-    tonemap_tex: wgpu::Texture,
-    cubemap: wgpu::Texture,
+    // Base pose vertices of skinned and morphed meshes.
+    animated_vertex_buffers: buffers::AnimatedVertexBuffers,
+    morph_target_deltas: buffers::AllocatedBuffer<animation::MorphTargetDelta>,
+    joint_matrices: buffers::VecGpuBuffer<glam::Mat4>,
+    morph_weights: buffers::VecGpuBuffer<f32>,
+    tonemap_tex: texture_loading::LoadedTexture,
+    cubemap: texture_loading::LoadedTexture,
+    ibl: ibl::ImageBasedLighting,
+    shadows: shadows::ShadowMaps,
+    // Along with their formats in the render graph.
+    graph_inputs: Vec<(texture_loading::LoadedTexture, rps::Format)>,
     filter_constants: [f32; 2],
     skybox_boost: f32,
     platform: Platform,
-    window: Window,
+    // `None` when rendering headlessly.
+    window: Option<Window>,
     egui_rpass: RenderPass,
-    queue: wgpu::Queue,
+    queue: Arc<wgpu::Queue>,
     bindless_textures: BindlessTextures,
-    model_info_buffer: buffers::VecGpuBuffer<MaterialInfo>,
+    texture_streamer: texture_streaming::TextureStreamer,
+    model_info_buffer: buffers::AllocatedBuffer<MaterialInfo>,
+    // Keyed by the versions of `bindless_textures`, `model_info_buffer` and the light buffer.
+    moon_bind_group: pipelines::CachedBindGroup<(u64, u64, u64)>,
 }
 
-struct CommandBuffer {
-    encoder: Option<wgpu::CommandEncoder>,
-}
-
-#[derive(StructOpt)]
-struct Opts {
-    filename: PathBuf,
-    entry_point: String,
-}
-
-pub fn bind_node_callback(
-    subprogram: rps::Subprogram,
-    entry_point: &str,
-    callback: rps::PfnCmdCallback,
-    user_context: *mut c_void,
-) -> Result<(), rps::Result> {
-    let entry_point = std::ffi::CString::new(entry_point).unwrap();
-
-    unsafe {
-        rps::program_bind_node_callback(
-            subprogram,
-            entry_point.as_ptr(),
-            &rps::CmdCallback {
-                pfn_callback: callback,
-                user_context,
-                ..Default::default()
-            },
-        )
-    }
-}
-
-use reflection::ReflectionSettings;
-
-fn main() -> anyhow::Result<()> {
-    unsafe {
-        let opts = Opts::from_args();
-
-        let file_stem = opts.filename.file_stem().unwrap().to_str().unwrap();
-
-        let lib = unsafe { libloading::Library::new(&opts.filename).unwrap() };
-        let entry_name = format!("rpsl_M_{}_E_{}", file_stem, opts.entry_point);
-        let entry = rps::load_dynamic_library_and_get_entry_point(&lib, &entry_name).unwrap();
-
-        let start = std::time::Instant::now();
-
-        let event_loop = winit::event_loop::EventLoop::new();
-        let window = winit::window::Window::new(&event_loop).unwrap();
-
-        let instance = wgpu::Instance::default();
-
-        let surface = unsafe { instance.create_surface(&window) }.unwrap();
-
-        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
-            power_preference: wgpu::PowerPreference::HighPerformance,
-            force_fallback_adapter: false,
-            compatible_surface: Some(&surface),
-        }))
-        .unwrap();
-
-        dbg!(&adapter.get_info());
-
-        let (device, queue) = pollster::block_on(adapter.request_device(
-            &wgpu::DeviceDescriptor {
-                features: wgpu::Features::PUSH_CONSTANTS
-                    | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
-                    | wgpu::Features::SPIRV_SHADER_PASSTHROUGH
-                    | wgpu::Features::TEXTURE_BINDING_ARRAY
-                    | wgpu::Features::PARTIALLY_BOUND_BINDING_ARRAY,
-                limits: wgpu::Limits {
-                    max_push_constant_size: 64 * 2,
-                    max_sampled_textures_per_shader_stage: 4096,
-                    max_texture_dimension_2d: 16384,
-                    max_storage_textures_per_shader_stage: 6,
-                    max_compute_invocations_per_workgroup: 512,
-                    max_compute_workgroup_size_x: 512,
-                    ..Default::default()
-                },
-                ..Default::default()
-            },
-            None,
-        ))
-        .unwrap();
-
-        let device = Arc::new(device);
-
-        let mut vertex_buffers = buffers::VertexBuffers::new(1024, &device);
+impl UserData {
+    fn new(
+        device: Arc<wgpu::Device>,
+        queue: wgpu::Queue,
+        scene: &scene::Scene,
+        output_format: wgpu::TextureFormat,
+        window: Option<Window>,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Self> {
+        let queue = Arc::new(queue);
+
+        let mut vertex_buffers = buffers::VertexBuffers::new(1024, &device, "vertex buffers");
+        let animated_vertex_buffers =
+            buffers::AnimatedVertexBuffers::new(1024, &device, "animated vertex buffers");
+        let morph_target_deltas = buffers::AllocatedBuffer::new(
+            1024,
+            &device,
+            wgpu::BufferUsages::STORAGE,
+            "morph target delta buffer",
+        );
         let mut index_buffer = buffers::IndexBuffer::new(1024, &device);
 
-        let mut textures = BindlessTextures::new();
+        let mut textures = BindlessTextures::new(&device, &queue);
 
-        let mut model_info_buffer = buffers::VecGpuBuffer::new(
+        let model_info_buffer = buffers::AllocatedBuffer::new(
             1024,
             &device,
             wgpu::BufferUsages::STORAGE,
             "Material Info buffer",
         );
 
-        let swapchain_capabilities = surface.get_capabilities(&adapter);
-        let swapchain_format = swapchain_capabilities.formats[0];
+        let texture_streamer = texture_streaming::TextureStreamer::new(
+            device.clone(),
+            queue.clone(),
+            Some(Arc::new(mip_generation::MipGenerator::new(&device))),
+        );
 
-        let ((moon, bloom), (tex, cubemap)) = rayon::join(
-            || {
-                (
-                    load_gltf(
-                        "assets/moon.gltf",
-                        &vertex_buffers,
-                        &index_buffer,
-                        &device,
-                        &queue,
-                        &mut textures,
-                        &mut model_info_buffer,
-                    ),
-                    load_gltf(
-                        "assets/bloom_example.gltf",
-                        &vertex_buffers,
-                        &index_buffer,
-                        &device,
-                        &queue,
-                        &mut textures,
-                        &mut model_info_buffer,
-                    ),
+        let load_ktx2_file =
+            |path: &std::path::Path| -> anyhow::Result<texture_loading::LoadedTexture> {
+                let bytes = std::fs::read(path).map_err(|error| {
+                    anyhow::anyhow!("Failed to read {}: {}", path.display(), error)
+                })?;
+
+                load_ktx2(&bytes, &device, &queue, wgpu::TextureUsages::empty(), None).map_err(
+                    |error| anyhow::anyhow!("Failed to load {}: {}", path.display(), error),
                 )
-            },
+            };
+
+        let (models, (tex, cubemap)) = rayon::join(
             || {
-                rayon::join(
-                    || {
-                        load_ktx2(
-                            &std::fs::read("assets/tony-mc-mapface.ktx2").unwrap(),
+                scene
+                    .models
+                    .iter()
+                    .map(|model| {
+                        load_gltf(
+                            &model.path,
+                            &vertex_buffers,
+                            &animated_vertex_buffers,
+                            &morph_target_deltas,
+                            &index_buffer,
                             &device,
                             &queue,
+                            &mut textures,
+                            &model_info_buffer,
+                            &texture_streamer,
                         )
-                    },
-                    || load_ktx2(&std::fs::read("assets/hdr.ktx2").unwrap(), &device, &queue),
+                        .map(Some)
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()
+            },
+            || {
+                rayon::join(
+                    || load_ktx2_file(&scene.tonemap_lut),
+                    || load_ktx2_file(&scene.skybox),
                 )
             },
         );
 
-        let moon = moon.unwrap();
-        let bloom = bloom.unwrap();
+        let models = models?;
+        let tex = tex?;
+        let cubemap = cubemap?;
 
-        let kernel = load_ktx2(&std::fs::read("kernel3.ktx2").unwrap(), &device, &queue);
+        let graph_inputs = scene
+            .graph_inputs
+            .iter()
+            .map(|path| {
+                let texture = load_ktx2_file(path)?;
+                let format = texture.texture.format();
+
+                let rps_format = map_wgpu_format_to_rps(format).ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "{} can't be a graph input, as {:?} isn't supported by the render graph",
+                        path.display(),
+                        format
+                    )
+                })?;
+
+                Ok((texture, rps_format))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut instances = instances::Instances::new(1024, &device);
+        let mut spinning_instances = Vec::new();
+
+        for (i, model) in scene.models.iter().enumerate() {
+            let transform = &model.transform;
+
+            let handle = instances.insert(
+                instances::ModelHandle(i),
+                instances::Instance {
+                    position: transform.position,
+                    scale: transform.scale,
+                    rotation: transform.rotation,
+                },
+            );
 
-        let mut keyboard_state = KeyboardState::default();
-        let mut fullscreen = false;
+            if transform.spin != 0.0 {
+                spinning_instances.push((handle, transform.spin));
+            }
+        }
 
-        //let mut cursor_grab = false;
+        let joint_matrices = buffers::VecGpuBuffer::new(
+            256,
+            &device,
+            wgpu::BufferUsages::STORAGE,
+            "joint matrix buffer",
+        );
 
-        let mut camera_rig: dolly::rig::CameraRig = dolly::rig::CameraRig::builder()
-            .with(dolly::drivers::Position::new(dolly::glam::Vec3::new(
-                2.0, 4.0, 1.0,
-            )))
-            .with(dolly::drivers::YawPitch::new().pitch_degrees(-74.0))
-            .with(dolly::drivers::Smooth::new_position_rotation(0.5, 0.5))
-            .build();
+        let morph_weights = buffers::VecGpuBuffer::new(
+            256,
+            &device,
+            wgpu::BufferUsages::STORAGE,
+            "morph weight buffer",
+        );
 
-        let size = window.inner_size();
+        let lights = lights::Lights::new(
+            scene
+                .lights
+                .iter()
+                .map(scene::SceneLight::to_light)
+                .collect(),
+            &device,
+        );
 
-        let mut config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: swapchain_format,
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
-            alpha_mode: swapchain_capabilities.alpha_modes[0],
-            view_formats: vec![],
-        };
+        let ibl = ibl::ImageBasedLighting::new(&device);
+        let shadows = shadows::ShadowMaps::new(&device);
 
-        surface.configure(&device, &config);
+        let camera_rig = scene.camera.create_rig();
 
-        let mut egui_rpass = RenderPass::new(&device, swapchain_format, 1);
-        let mut platform = Platform::new(PlatformDescriptor {
-            physical_width: size.width as u32,
-            physical_height: size.height as u32,
-            scale_factor: window.scale_factor(),
+        let egui_rpass = RenderPass::new(&device, output_format, 1);
+        let platform = Platform::new(PlatformDescriptor {
+            physical_width: width,
+            physical_height: height,
+            scale_factor: window
+                .as_ref()
+                .map(|window| window.scale_factor())
+                .unwrap_or(1.0),
             font_definitions: Default::default(),
             style: Default::default(),
         });
 
-        let user_data_raw = box_untyped(UserData {
+        Ok(Self {
             sampler: device.create_sampler(&wgpu::SamplerDescriptor {
                 mag_filter: wgpu::FilterMode::Linear,
                 min_filter: wgpu::FilterMode::Linear,
@@ -251,28 +262,647 @@ fn main() -> anyhow::Result<()> {
                 address_mode_w: wgpu::AddressMode::Repeat,
                 ..Default::default()
             }),
-            device: device.clone(),
+            device,
             queue,
             camera_rig,
             tonemap_tex: tex,
             cubemap,
+            ibl,
+            shadows,
+            graph_inputs,
             index_buffer,
             vertex_buffers,
-            moon,
-            bloom,
+            animated_vertex_buffers,
+            morph_target_deltas,
+            joint_matrices,
+            morph_weights,
+            models,
+            instances,
+            spinning_instances,
+            lights,
             filter_constants: [1.25; 2],
             skybox_boost: 50.0,
             platform,
             window,
             egui_rpass,
             bindless_textures: textures,
+            texture_streamer,
             model_info_buffer,
-            rotation: 0.0,
-        });
+            moon_bind_group: Default::default(),
+        })
+    }
+
+    // Advance any animated state by a frame.
+    fn update(&mut self, delta_time: f32) {
+        for &(handle, spin) in &self.spinning_instances {
+            if let Some(instance) = self.instances.get_mut(handle) {
+                instance.rotation =
+                    glam::Quat::from_rotation_y(spin * delta_time) * instance.rotation;
+            }
+        }
+
+        for model in self.models.iter_mut().flatten() {
+            if let Some(animation) = &mut model.animation {
+                animation.update(delta_time);
+            }
+        }
+
+        self.texture_streamer.update(&mut self.bindless_textures);
+    }
+
+    // Remove a model and all of its instances, freeing its geometry, materials and textures.
+    fn unload_model(&mut self, handle: instances::ModelHandle) {
+        let model = match self.models.get_mut(handle.0).and_then(Option::take) {
+            Some(model) => model,
+            None => return,
+        };
+
+        self.instances.remove_model(handle);
+
+        let instances = &self.instances;
+        self.spinning_instances
+            .retain(|&(instance, _)| instances.get(instance).is_some());
+
+        model_loading::unload_model(
+            model,
+            &self.vertex_buffers,
+            &self.animated_vertex_buffers,
+            &self.morph_target_deltas,
+            &self.index_buffer,
+            &mut self.bindless_textures,
+            &self.model_info_buffer,
+        );
+    }
+
+    // Move the live ranges of the geometry buffers together so that the space freed by unloaded
+    // models can be used for larger allocations. Material infos aren't compacted, as vertices
+    // refer to them by absolute index.
+    fn compact_buffers(&mut self, command_encoder: &mut wgpu::CommandEncoder) {
+        let vertices = self.vertex_buffers.compact(&self.device, command_encoder);
+        let animated_vertices = self
+            .animated_vertex_buffers
+            .compact(&self.device, command_encoder);
+        let morph_target_deltas = self
+            .morph_target_deltas
+            .compact(&self.device, command_encoder);
+        let indices = self.index_buffer.compact(&self.device, command_encoder);
+
+        for model in self.models.iter_mut().flatten() {
+            model.remap(
+                &vertices,
+                &animated_vertices,
+                &morph_target_deltas,
+                &indices,
+            );
+        }
+
+        self.instances.invalidate();
+
+        log::info!("Compacted vertex buffers: {}", self.vertex_buffers.stats());
+        log::info!(
+            "Compacted animated vertex buffers: {}",
+            self.animated_vertex_buffers.stats()
+        );
+        log::info!(
+            "Compacted morph target delta buffer: {}",
+            self.morph_target_deltas.stats()
+        );
+        log::info!("Compacted index buffer: {}", self.index_buffer.stats());
+    }
+
+    // Block until all the textures that are loading in the background are ready, so that
+    // headless frames are deterministic.
+    fn finish_loading_textures(&mut self) {
+        self.texture_streamer
+            .wait_for_all(&mut self.bindless_textures);
+    }
+}
+
+struct CommandBuffer {
+    encoder: Option<wgpu::CommandEncoder>,
+}
+
+#[derive(StructOpt)]
+struct Opts {
+    filename: PathBuf,
+    entry_point: String,
+    /// The scene description to load.
+    #[structopt(long, parse(from_os_str), default_value = "scenes/default.ron")]
+    scene: PathBuf,
+    /// Render offscreen without creating a window, writing each frame to `--output`.
+    #[structopt(long)]
+    headless: bool,
+    /// The number of frames to render in headless mode.
+    #[structopt(long, default_value = "1")]
+    frames: u32,
+    /// The directory that headless frames are written to.
+    #[structopt(long, parse(from_os_str), default_value = "output")]
+    output: PathBuf,
+    /// The image format of headless frames (`png` or `exr`).
+    #[structopt(long, default_value = "png")]
+    format: headless::OutputFormat,
+    #[structopt(long, default_value = "1280")]
+    width: u32,
+    #[structopt(long, default_value = "720")]
+    height: u32,
+    /// Request a software adapter, e.g. for rendering on CI machines without a GPU.
+    #[structopt(long)]
+    force_fallback_adapter: bool,
+}
+
+pub fn bind_node_callback(
+    subprogram: rps::Subprogram,
+    entry_point: &str,
+    callback: rps::PfnCmdCallback,
+    user_context: *mut c_void,
+) -> Result<(), rps::Result> {
+    let entry_point = std::ffi::CString::new(entry_point).unwrap();
+
+    unsafe {
+        rps::program_bind_node_callback(
+            subprogram,
+            entry_point.as_ptr(),
+            &rps::CmdCallback {
+                pfn_callback: callback,
+                user_context,
+                ..Default::default()
+            },
+        )
+    }
+}
+
+use reflection::ReflectionSettings;
+
+// The library needs to outlive the entry point, so it's returned alongside it.
+fn load_entry_point(
+    filename: &Path,
+    entry_point: &str,
+) -> anyhow::Result<(libloading::Library, rps::RpslEntry)> {
+    let file_stem = filename
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| anyhow::anyhow!("Invalid module filename: {}", filename.display()))?;
+
+    let lib = unsafe { libloading::Library::new(filename)? };
+    let entry_name = format!("rpsl_M_{}_E_{}", file_stem, entry_point);
+    let entry = unsafe { rps::load_dynamic_library_and_get_entry_point(&lib, &entry_name) }
+        .map_err(|error| anyhow::anyhow!("Failed to load {}: {:?}", entry_name, error))?;
+
+    Ok((lib, entry))
+}
+
+fn request_device(adapter: &wgpu::Adapter) -> anyhow::Result<(wgpu::Device, wgpu::Queue)> {
+    log::info!("Using adapter: {:?}", adapter.get_info());
+
+    Ok(pollster::block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            features: wgpu::Features::PUSH_CONSTANTS
+                | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                | wgpu::Features::SPIRV_SHADER_PASSTHROUGH
+                | wgpu::Features::TEXTURE_BINDING_ARRAY
+                | wgpu::Features::PARTIALLY_BOUND_BINDING_ARRAY
+                // Compressed textures are decompressed on the CPU if these aren't available,
+                // except for ASTC HDR, which can only be loaded with the feature.
+                | (adapter.features()
+                    & (wgpu::Features::TEXTURE_COMPRESSION_BC
+                        | wgpu::Features::TEXTURE_COMPRESSION_ASTC
+                        | wgpu::Features::TEXTURE_COMPRESSION_ASTC_HDR)),
+            limits: wgpu::Limits {
+                max_push_constant_size: 64 * 2,
+                max_sampled_textures_per_shader_stage: 4096,
+                max_texture_dimension_2d: 16384,
+                max_storage_textures_per_shader_stage: 6,
+                max_storage_buffers_per_shader_stage: 12,
+                max_compute_invocations_per_workgroup: 512,
+                max_compute_workgroup_size_x: 512,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        None,
+    ))?)
+}
+
+fn create_node_callbacks(
+    device: &wgpu::Device,
+    output_format: wgpu::TextureFormat,
+) -> Vec<(&'static str, Callback, *mut c_void)> {
+    vec![
+        (
+            "blit_srgb",
+            node_callbacks::blit_srgb,
+            box_untyped(RenderPipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/blit_srgb.hlsl"),
+                "VSMain",
+                "PSMain",
+                &[Some(output_format.into())],
+                None,
+                &[],
+                &Default::default(),
+                false,
+            )),
+        ),
+        (
+            "draw",
+            node_callbacks::draw,
+            box_untyped(RenderPipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/moon.hlsl"),
+                "VSMain",
+                "PSMain",
+                &[Some(wgpu::TextureFormat::Rgba16Float.into())],
+                Some(wgpu::DepthStencilState {
+                    format: wgpu::TextureFormat::Depth32Float,
+                    depth_write_enabled: true,
+                    // Equal depths pass so that surfaces already in the depth prepass are drawn.
+                    depth_compare: wgpu::CompareFunction::GreaterEqual,
+                    stencil: Default::default(),
+                    bias: Default::default(),
+                }),
+                &[
+                    // position, normal, uv, lightmap uv
+                    wgpu::VertexBufferLayout {
+                        array_stride: 3 * 4,
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                        attributes: &wgpu::vertex_attr_array![0 => Float32x3],
+                    },
+                    wgpu::VertexBufferLayout {
+                        array_stride: 2 * 4,
+                        attributes: &wgpu::vertex_attr_array![1 => Float32x2],
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                    },
+                    wgpu::VertexBufferLayout {
+                        array_stride: 3 * 4,
+                        attributes: &wgpu::vertex_attr_array![2 => Float32x3],
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                    },
+                    wgpu::VertexBufferLayout {
+                        array_stride: 4,
+                        attributes: &wgpu::vertex_attr_array![3 => Uint32],
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                    },
+                    // Instance transform matrix, column by column.
+                    wgpu::VertexBufferLayout {
+                        array_stride: std::mem::size_of::<glam::Mat4>() as u64,
+                        attributes: &wgpu::vertex_attr_array![4 => Float32x4, 5 => Float32x4, 6 => Float32x4, 7 => Float32x4],
+                        step_mode: wgpu::VertexStepMode::Instance,
+                    },
+                    // second uv, tangent, color
+                    wgpu::VertexBufferLayout {
+                        array_stride: 2 * 4,
+                        attributes: &wgpu::vertex_attr_array![8 => Float32x2],
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                    },
+                    wgpu::VertexBufferLayout {
+                        array_stride: 4 * 4,
+                        attributes: &wgpu::vertex_attr_array![9 => Float32x4],
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                    },
+                    wgpu::VertexBufferLayout {
+                        array_stride: 4 * 4,
+                        attributes: &wgpu::vertex_attr_array![10 => Float32x4],
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                    },
+                ],
+                &ReflectionSettings {
+                    override_sampled_texture_ty: Some((8, wgpu::TextureSampleType::Depth)),
+                    comparison_sampler: Some(9),
+                },
+                true,
+            )),
+        ),
+        (
+            "animate_vertices",
+            node_callbacks::animate_vertices,
+            box_untyped(ComputePipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/animate_vertices.hlsl"),
+                "animate_vertices",
+                &Default::default(),
+                false,
+            )),
+        ),
+        (
+            "depth_prepass",
+            node_callbacks::depth_prepass,
+            box_untyped(RenderPipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/depth_prepass.hlsl"),
+                "VSMain",
+                "PSMain",
+                &[],
+                Some(wgpu::DepthStencilState {
+                    format: wgpu::TextureFormat::Depth32Float,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Greater,
+                    stencil: Default::default(),
+                    bias: Default::default(),
+                }),
+                &[
+                    wgpu::VertexBufferLayout {
+                        array_stride: 3 * 4,
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                        attributes: &wgpu::vertex_attr_array![0 => Float32x3],
+                    },
+                    wgpu::VertexBufferLayout {
+                        array_stride: std::mem::size_of::<glam::Mat4>() as u64,
+                        attributes: &wgpu::vertex_attr_array![1 => Float32x4, 2 => Float32x4, 3 => Float32x4, 4 => Float32x4],
+                        step_mode: wgpu::VertexStepMode::Instance,
+                    },
+                ],
+                &Default::default(),
+                true,
+            )),
+        ),
+        (
+            "render_shadows",
+            node_callbacks::render_shadows,
+            box_untyped(RenderPipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/depth_prepass.hlsl"),
+                "VSMain",
+                "PSMain",
+                &[],
+                Some(wgpu::DepthStencilState {
+                    format: wgpu::TextureFormat::Depth32Float,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: Default::default(),
+                    bias: Default::default(),
+                }),
+                &[
+                    wgpu::VertexBufferLayout {
+                        array_stride: 3 * 4,
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                        attributes: &wgpu::vertex_attr_array![0 => Float32x3],
+                    },
+                    wgpu::VertexBufferLayout {
+                        array_stride: std::mem::size_of::<glam::Mat4>() as u64,
+                        attributes: &wgpu::vertex_attr_array![1 => Float32x4, 2 => Float32x4, 3 => Float32x4, 4 => Float32x4],
+                        step_mode: wgpu::VertexStepMode::Instance,
+                    },
+                ],
+                &Default::default(),
+                true,
+            )),
+        ),
+        (
+            "cull_lights",
+            node_callbacks::cull_lights,
+            box_untyped(ComputePipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/cull_lights.hlsl"),
+                "cull_lights",
+                &ReflectionSettings {
+                    override_sampled_texture_ty: Some((0, wgpu::TextureSampleType::Depth)),
+                    comparison_sampler: None,
+                },
+                true,
+            )),
+        ),
+        (
+            "prefilter_specular",
+            node_callbacks::prefilter_specular,
+            box_untyped(ComputePipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/prefilter_specular.hlsl"),
+                "prefilter_specular",
+                &Default::default(),
+                false,
+            )),
+        ),
+        (
+            "compute_irradiance",
+            node_callbacks::compute_irradiance,
+            box_untyped(ComputePipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/compute_irradiance.hlsl"),
+                "compute_irradiance",
+                &Default::default(),
+                false,
+            )),
+        ),
+        (
+            "integrate_brdf",
+            node_callbacks::integrate_brdf,
+            box_untyped(ComputePipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/integrate_brdf.hlsl"),
+                "integrate_brdf",
+                &Default::default(),
+                false,
+            )),
+        ),
+        (
+            "downsample_initial",
+            node_callbacks::downsample_initial,
+            box_untyped(ComputePipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/downsample_initial.hlsl"),
+                "downsample_initial",
+                &Default::default(),
+                false,
+            )),
+        ),
+        (
+            "downsample",
+            node_callbacks::downsample,
+            box_untyped(ComputePipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/downsample.hlsl"),
+                "downsample",
+                &Default::default(),
+                false,
+            )),
+        ),
+        (
+            "upsample",
+            node_callbacks::downsample,
+            box_untyped(ComputePipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/upsample.hlsl"),
+                "upsample",
+                &Default::default(),
+                false,
+            )),
+        ),
+        (
+            "tonemap",
+            node_callbacks::tonemap,
+            box_untyped(ComputePipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/tonemap.hlsl"),
+                "tonemap",
+                &Default::default(),
+                false,
+            )),
+        ),
+        (
+            "compute_dof",
+            node_callbacks::compute_dof,
+            box_untyped(ComputePipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/compute_dof.hlsl"),
+                "compute_dof",
+                &ReflectionSettings {
+                    override_sampled_texture_ty: Some((0, wgpu::TextureSampleType::Depth)),
+                    comparison_sampler: None,
+                },
+                true,
+            )),
+        ),
+        (
+            "render_skybox",
+            node_callbacks::render_skybox,
+            box_untyped(RenderPipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/skybox.hlsl"),
+                "VSMain",
+                "PSMain",
+                &[Some(wgpu::TextureFormat::Rgba16Float.into())],
+                Some(wgpu::DepthStencilState {
+                    format: wgpu::TextureFormat::Depth32Float,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Equal,
+                    stencil: Default::default(),
+                    bias: Default::default(),
+                }),
+                &[],
+                &Default::default(),
+                false,
+            )),
+        ),
+        (
+            "render_ui",
+            node_callbacks::render_ui,
+            box_untyped(666.6665_f32),
+        ),
+        (
+            "dof_downsample_with_coc",
+            node_callbacks::dof_downsample_with_coc,
+            box_untyped(ComputePipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/dof_downsample_with_coc.hlsl"),
+                "dof_downsample_with_coc",
+                &ReflectionSettings {
+                    override_sampled_texture_ty: Some((0, wgpu::TextureSampleType::Depth)),
+                    comparison_sampler: None,
+                },
+                true,
+            )),
+        ),
+        (
+            "dof_x",
+            node_callbacks::dof_x,
+            box_untyped(ComputePipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/dof_x.hlsl"),
+                "dof_x",
+                &Default::default(),
+                false,
+            )),
+        ),
+        (
+            "dof_y",
+            node_callbacks::dof_y,
+            box_untyped(ComputePipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/dof_y.hlsl"),
+                "dof_y",
+                &Default::default(),
+                false,
+            )),
+        ),
+        (
+            "fft_horizontal_forwards",
+            node_callbacks::fft_horizontal_forwards,
+            box_untyped(ComputePipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/FFTConvolutionBloom/Assets/Shaders/FFTCS.compute"),
+                "TwoForOneFFTForwardHorizontal",
+                &Default::default(),
+                true,
+            )),
+        ),
+        (
+            "blit_compute",
+            node_callbacks::blit_compute,
+            box_untyped(ComputePipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/blit_compute.hlsl"),
+                "blit_compute",
+                &Default::default(),
+                false,
+            )),
+        ),
+        (
+            "fft_vertical",
+            node_callbacks::fft_vertical,
+            box_untyped(ComputePipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/FFTConvolutionBloom/Assets/Shaders/FFTCS.compute"),
+                "FFTVertical",
+                &Default::default(),
+                true,
+            )),
+        ),
+        (
+            "fft_horizontal_inverse",
+            node_callbacks::fft_horizontal_forwards,
+            box_untyped(ComputePipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/FFTConvolutionBloom/Assets/Shaders/FFTCS.compute"),
+                "TwoForOneFFTInverseHorizontal",
+                &Default::default(),
+                true,
+            )),
+        ),
+        (
+            "fft_convolute",
+            node_callbacks::fft_convolute,
+            box_untyped(ComputePipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/FFTConvolutionBloom/Assets/Shaders/FFTCS.compute"),
+                "Convolution",
+                &Default::default(),
+                true,
+            )),
+        ),
+        (
+            "fft_kernel_transform",
+            node_callbacks::fft_kernel_transform,
+            box_untyped(ComputePipeline::new(
+                &device,
+                &ShaderSource::Hlsl("shaders/FFTConvolutionBloom/Assets/Shaders/FFTCS.compute"),
+                "KernelTransform",
+                &Default::default(),
+                true,
+            )),
+        ),
+    ]
+}
+
+struct Renderer {
+    graph: rps::RenderGraph,
+    user_data_raw: *mut c_void,
+    frame_index: u64,
+    completed_frame_index: u64,
+}
+
+impl Renderer {
+    unsafe fn new(
+        entry: rps::RpslEntry,
+        user_data: UserData,
+        output_format: wgpu::TextureFormat,
+    ) -> Self {
+        let device = user_data.device.clone();
+
+        let user_data_raw = box_untyped(user_data);
 
         let device_create_info = rps::DeviceCreateInfo::default();
 
-        let rps_device = unsafe { rps::device_create(&device_create_info) }.unwrap();
+        let rps_device = rps::device_create(&device_create_info).unwrap();
 
         rps_custom_backend::add_callback_runtime(
             &rps_device,
@@ -292,7 +922,7 @@ fn main() -> anyhow::Result<()> {
 
         let queues = &[rps::QueueFlags::all()];
 
-        let mut x = rps::RenderGraphCreateInfo {
+        let x = rps::RenderGraphCreateInfo {
             schedule_info: rps::RenderGraphCreateScheduleInfo {
                 queue_infos: queues.as_ptr(),
                 num_queues: queues.len() as u32,
@@ -308,7 +938,7 @@ fn main() -> anyhow::Result<()> {
             },
             ..Default::default()
         };
-        let graph = unsafe { rps::render_graph_create(rps_device, &x) }.unwrap();
+        let graph = rps::render_graph_create(rps_device, &x).unwrap();
 
         let subprogram = rps::render_graph_get_main_entry(graph);
 
@@ -322,271 +952,201 @@ fn main() -> anyhow::Result<()> {
             .map(|node| std::ffi::CStr::from_ptr(node.name).to_str().unwrap())
             .collect();
 
-        let callbacks: &[(&str, Callback, *mut c_void)] = &[
-            (
-                "blit_srgb",
-                node_callbacks::blit_srgb,
-                box_untyped(RenderPipeline::new(
-                    &device,
-                    &ShaderSource::Hlsl("shaders/blit_srgb.hlsl"),
-                    "VSMain",
-                    "PSMain",
-                    &[Some(swapchain_format.into())],
-                    None,
-                    &[],
-                    false,
-                )),
-            ),
-            (
-                "draw",
-                node_callbacks::draw,
-                box_untyped(RenderPipeline::new(
-                    &device,
-                    &ShaderSource::Hlsl("shaders/moon.hlsl"),
-                    "VSMain",
-                    "PSMain",
-                    &[Some(wgpu::TextureFormat::Rgba16Float.into())],
-                    Some(wgpu::DepthStencilState {
-                        format: wgpu::TextureFormat::Depth32Float,
-                        depth_write_enabled: true,
-                        depth_compare: wgpu::CompareFunction::Greater,
-                        stencil: Default::default(),
-                        bias: Default::default(),
-                    }),
-                    &[
-                        // position, normal, uv, lightmap uv
-                        wgpu::VertexBufferLayout {
-                            array_stride: 3 * 4,
-                            step_mode: wgpu::VertexStepMode::Vertex,
-                            attributes: &wgpu::vertex_attr_array![0 => Float32x3],
-                        },
-                        wgpu::VertexBufferLayout {
-                            array_stride: 2 * 4,
-                            attributes: &wgpu::vertex_attr_array![1 => Float32x2],
-                            step_mode: wgpu::VertexStepMode::Vertex,
-                        },
-                        wgpu::VertexBufferLayout {
-                            array_stride: 3 * 4,
-                            attributes: &wgpu::vertex_attr_array![2 => Float32x3],
-                            step_mode: wgpu::VertexStepMode::Vertex,
-                        },
-                        wgpu::VertexBufferLayout {
-                            array_stride: 4,
-                            attributes: &wgpu::vertex_attr_array![3 => Uint32],
-                            step_mode: wgpu::VertexStepMode::Vertex,
-                        },
-                        wgpu::VertexBufferLayout {
-                            array_stride: std::mem::size_of::<[f32; 8]>() as u64,
-                            attributes: &wgpu::vertex_attr_array![4 => Float32x3, 5 => Float32, 6 => Float32x4],
-                            step_mode: wgpu::VertexStepMode::Instance,
-                        },
-                    ],
-                    true,
-                )),
-            ),
-            (
-                "downsample_initial",
-                node_callbacks::downsample_initial,
-                box_untyped(ComputePipeline::new(
-                    &device,
-                    &ShaderSource::Hlsl("shaders/downsample_initial.hlsl"),
-                    "downsample_initial",
-                    &Default::default(),
-                    false,
-                )),
-            ),
-            (
-                "downsample",
-                node_callbacks::downsample,
-                box_untyped(ComputePipeline::new(
-                    &device,
-                    &ShaderSource::Hlsl("shaders/downsample.hlsl"),
-                    "downsample",
-                    &Default::default(),
-                    false,
-                )),
-            ),
-            (
-                "upsample",
-                node_callbacks::downsample,
-                box_untyped(ComputePipeline::new(
-                    &device,
-                    &ShaderSource::Hlsl("shaders/upsample.hlsl"),
-                    "upsample",
-                    &Default::default(),
-                    false,
-                )),
-            ),
-            (
-                "tonemap",
-                node_callbacks::tonemap,
-                box_untyped(ComputePipeline::new(
-                    &device,
-                    &ShaderSource::Hlsl("shaders/tonemap.hlsl"),
-                    "tonemap",
-                    &Default::default(),
-                    false,
-                )),
-            ),
-            (
-                "compute_dof",
-                node_callbacks::compute_dof,
-                box_untyped(ComputePipeline::new(
-                    &device,
-                    &ShaderSource::Hlsl("shaders/compute_dof.hlsl"),
-                    "compute_dof",
-                    &ReflectionSettings {
-                        override_sampled_texture_ty: Some((0, wgpu::TextureSampleType::Depth)),
-                    },
-                    true,
-                )),
-            ),
-            (
-                "render_skybox",
-                node_callbacks::render_skybox,
-                box_untyped(RenderPipeline::new(
-                    &device,
-                    &ShaderSource::Hlsl("shaders/skybox.hlsl"),
-                    "VSMain",
-                    "PSMain",
-                    &[Some(wgpu::TextureFormat::Rgba16Float.into())],
-                    Some(wgpu::DepthStencilState {
-                        format: wgpu::TextureFormat::Depth32Float,
-                        depth_write_enabled: false,
-                        depth_compare: wgpu::CompareFunction::Equal,
-                        stencil: Default::default(),
-                        bias: Default::default(),
-                    }),
-                    &[],
-                    false,
-                )),
-            ),
-            (
-                "render_ui",
-                node_callbacks::render_ui,
-                box_untyped(666.6665_f32),
-            ),
-            (
-                "dof_downsample_with_coc",
-                node_callbacks::dof_downsample_with_coc,
-                box_untyped(ComputePipeline::new(
-                    &device,
-                    &ShaderSource::Hlsl("shaders/dof_downsample_with_coc.hlsl"),
-                    "dof_downsample_with_coc",
-                    &ReflectionSettings {
-                        override_sampled_texture_ty: Some((0, wgpu::TextureSampleType::Depth)),
-                    },
-                    true,
-                )),
-            ),
-            (
-                "dof_x",
-                node_callbacks::dof_x,
-                box_untyped(ComputePipeline::new(
-                    &device,
-                    &ShaderSource::Hlsl("shaders/dof_x.hlsl"),
-                    "dof_x",
-                    &Default::default(),
-                    false,
-                )),
-            ),
-            (
-                "dof_y",
-                node_callbacks::dof_y,
-                box_untyped(ComputePipeline::new(
-                    &device,
-                    &ShaderSource::Hlsl("shaders/dof_y.hlsl"),
-                    "dof_y",
-                    &Default::default(),
-                    false,
-                )),
-            ),
-            (
-                "fft_horizontal_forwards",
-                node_callbacks::fft_horizontal_forwards,
-                box_untyped(ComputePipeline::new(
-                    &device,
-                    &ShaderSource::Hlsl("shaders/FFTConvolutionBloom/Assets/Shaders/FFTCS.compute"),
-                    "TwoForOneFFTForwardHorizontal",
-                    &Default::default(),
-                    true,
-                )),
-            ),
-            (
-                "blit_compute",
-                node_callbacks::blit_compute,
-                box_untyped(ComputePipeline::new(
-                    &device,
-                    &ShaderSource::Hlsl("shaders/blit_compute.hlsl"),
-                    "blit_compute",
-                    &Default::default(),
-                    false,
-                )),
-            ),
-            (
-                "fft_vertical",
-                node_callbacks::fft_vertical,
-                box_untyped(ComputePipeline::new(
-                    &device,
-                    &ShaderSource::Hlsl("shaders/FFTConvolutionBloom/Assets/Shaders/FFTCS.compute"),
-                    "FFTVertical",
-                    &Default::default(),
-                    true,
-                )),
-            ),
-            (
-                "fft_horizontal_inverse",
-                node_callbacks::fft_horizontal_forwards,
-                box_untyped(ComputePipeline::new(
-                    &device,
-                    &ShaderSource::Hlsl("shaders/FFTConvolutionBloom/Assets/Shaders/FFTCS.compute"),
-                    "TwoForOneFFTInverseHorizontal",
-                    &Default::default(),
-                    true,
-                )),
-            ),
-            (
-                "fft_convolute",
-                node_callbacks::fft_convolute,
-                box_untyped(ComputePipeline::new(
-                    &device,
-                    &ShaderSource::Hlsl("shaders/FFTConvolutionBloom/Assets/Shaders/FFTCS.compute"),
-                    "Convolution",
-                    &Default::default(),
-                    true,
-                )),
-            ),
-            (
-                "fft_kernel_transform",
-                node_callbacks::fft_kernel_transform,
-                box_untyped(ComputePipeline::new(
-                    &device,
-                    &ShaderSource::Hlsl("shaders/FFTConvolutionBloom/Assets/Shaders/FFTCS.compute"),
-                    "KernelTransform",
-                    &Default::default(),
-                    true,
-                )),
-            ),
-        ];
-
         node_names.remove("clear_color");
         node_names.remove("clear_depth_stencil");
 
-        for (name, callback, data) in callbacks {
+        for (name, callback, data) in create_node_callbacks(&device, output_format) {
             if node_names.remove(name) {
-                bind_node_callback(subprogram, name, Some(*callback), *data).unwrap()
+                bind_node_callback(subprogram, name, Some(callback), data).unwrap()
             }
         }
 
-        dbg!(node_names);
+        if !node_names.is_empty() {
+            log::warn!("Nodes without callbacks: {:?}", node_names);
+        }
+
+        Self {
+            graph,
+            user_data_raw,
+            frame_index: 0,
+            completed_frame_index: u64::max_value(),
+        }
+    }
+
+    fn user_data(&mut self) -> &mut UserData {
+        unsafe { &mut *(self.user_data_raw as *mut UserData) }
+    }
+
+    // Update and record the render graph, with `back_buffer` as the output.
+    unsafe fn render(
+        &mut self,
+        back_buffer: wgpu::TextureView,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) {
+        let user_data = &mut *(self.user_data_raw as *mut UserData);
+
+        let back_buffer_format = map_wgpu_format_to_rps(format)
+            .unwrap_or_else(|| panic!("Unsupported back buffer format: {:?}", format));
+
+        let mut arg_descs = vec![image_resource_desc(width, height, back_buffer_format)];
+        let mut arg_resource_ptrs = vec![box_untyped(Resource::SurfaceFrame(back_buffer))];
+
+        for (input, rps_format) in &user_data.graph_inputs {
+            arg_descs.push(image_resource_desc(
+                input.texture.width(),
+                input.texture.height(),
+                *rps_format,
+            ));
+            arg_resource_ptrs.push(box_untyped(Resource::SurfaceFrame(input.create_view())));
+        }
+
+        let args: Vec<rps::Constant> = arg_descs
+            .iter()
+            .map(|desc| desc as *const rps::ResourceDesc as _)
+            .collect();
+
+        let arg_resources: Vec<_> = arg_resource_ptrs
+            .iter()
+            .map(|ptr| ptr as *const *mut c_void as _)
+            .collect();
+
+        let update_info = rps::RenderGraphUpdateInfo {
+            frame_index: self.frame_index,
+            gpu_completed_frame_index: self.completed_frame_index,
+            diagnostic_flags: rps::DiagnosticFlags::empty(),
+            num_args: args.len() as u32,
+            args: args.as_ptr(),
+            arg_resources: arg_resources.as_ptr(),
+            ..Default::default()
+        };
+
+        rps::render_graph_update(self.graph, &update_info).unwrap();
+
+        let layout = rps::render_graph_get_batch_layout(self.graph).unwrap();
+
+        for batch in layout.cmd_batches() {
+            let encoder = user_data
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+
+            let mut cb = CommandBuffer {
+                encoder: Some(encoder),
+            };
+
+            let cb_ptr = &cb as *const CommandBuffer;
+
+            rps::render_graph_record_commands(
+                self.graph,
+                &rps::RenderGraphRecordCommandInfo {
+                    user_context: self.user_data_raw,
+                    cmd_buffer: rps::RuntimeCommandBuffer::from_raw(cb_ptr as _),
+                    frame_index: self.frame_index,
+                    cmd_begin_index: batch.cmd_begin,
+                    num_cmds: batch.num_cmds,
+                    flags: Default::default(),
+                },
+            )
+            .unwrap();
+
+            let encoder = cb.encoder.take().unwrap();
+
+            user_data.queue.submit(Some(encoder.finish()));
+        }
+
+        self.completed_frame_index = self.frame_index;
+        self.frame_index += 1;
+    }
+}
+
+fn image_resource_desc(width: u32, height: u32, format: rps::Format) -> rps::ResourceDesc {
+    rps::ResourceDesc {
+        ty: rps::ResourceType::IMAGE_2D,
+        temporal_layers: 1,
+        flags: Default::default(),
+        buffer_image: rps::ResourceBufferImageDesc {
+            image: rps::ResourceImageDesc {
+                width,
+                height,
+                mip_levels: 1,
+                sample_count: 1,
+                format,
+                depth_or_array_layers: 1,
+            },
+        },
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    unsafe {
+        let opts = Opts::from_args();
+
+        let (_lib, entry) = load_entry_point(&opts.filename, &opts.entry_point)?;
+
+        let scene = scene::Scene::load(&opts.scene)?;
+
+        if opts.headless {
+            return headless::run(entry, &scene, &opts);
+        }
+
+        let event_loop = winit::event_loop::EventLoop::new();
+        let window = winit::window::Window::new(&event_loop).unwrap();
+
+        let instance = wgpu::Instance::default();
+
+        let surface = instance.create_surface(&window).unwrap();
+
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter: opts.force_fallback_adapter,
+            compatible_surface: Some(&surface),
+        }))
+        .unwrap();
+
+        let (device, queue) = request_device(&adapter)?;
+
+        let device = Arc::new(device);
+
+        let swapchain_capabilities = surface.get_capabilities(&adapter);
+        let swapchain_format = swapchain_capabilities.formats[0];
+
+        let mut keyboard_state = KeyboardState::default();
+        let mut fullscreen = false;
+
+        //let mut cursor_grab = false;
+
+        let size = window.inner_size();
+
+        let mut config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: swapchain_format,
+            width: size.width,
+            height: size.height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: swapchain_capabilities.alpha_modes[0],
+            view_formats: vec![],
+        };
+
+        surface.configure(&device, &config);
+
+        let user_data = UserData::new(
+            device,
+            queue,
+            &scene,
+            swapchain_format,
+            Some(window),
+            size.width,
+            size.height,
+        )?;
+
+        let mut renderer = Renderer::new(entry, user_data, swapchain_format);
 
-        let mut completed_frame_index = u64::max_value();
-        let mut frame_index = 0;
-        let mut first_time = true;
         let start_time = Instant::now();
 
         event_loop.run(move |event, _, control_flow| {
-            let user_data = unsafe { &mut *(user_data_raw as *mut UserData) };
+            let user_data = &mut *(renderer.user_data_raw as *mut UserData);
+            let window = user_data.window.as_ref().unwrap();
             user_data.platform.handle_event(&event);
 
             match event {
@@ -613,11 +1173,10 @@ fn main() -> anyhow::Result<()> {
 
                                     if keyboard_state.cursor_grab {
                                         // Try both methods of grabbing the cursor.
-                                        let result = user_data
-                                            .window
+                                        let result = window
                                             .set_cursor_grab(winit::window::CursorGrabMode::Locked)
                                             .or_else(|_| {
-                                                user_data.window.set_cursor_grab(
+                                                window.set_cursor_grab(
                                                     winit::window::CursorGrabMode::Confined,
                                                 )
                                             });
@@ -630,13 +1189,10 @@ fn main() -> anyhow::Result<()> {
                                         }
                                     } else {
                                         // This can't fail.
-                                        let _ = user_data
-                                            .window
+                                        let _ = window
                                             .set_cursor_grab(winit::window::CursorGrabMode::None);
                                     }
-                                    user_data
-                                        .window
-                                        .set_cursor_visible(!keyboard_state.cursor_grab);
+                                    window.set_cursor_visible(!keyboard_state.cursor_grab);
                                 }
                             }
                             Some(VirtualKeyCode::LControl | VirtualKeyCode::RControl) => {
@@ -646,9 +1202,9 @@ fn main() -> anyhow::Result<()> {
                                 if pressed && keyboard_state.control {
                                     fullscreen = !fullscreen;
 
-                                    user_data.window.set_fullscreen(if fullscreen {
+                                    window.set_fullscreen(if fullscreen {
                                         Some(winit::window::Fullscreen::Borderless(Some(
-                                            user_data.window.current_monitor().unwrap(),
+                                            window.current_monitor().unwrap(),
                                         )))
                                     } else {
                                         None
@@ -664,7 +1220,7 @@ fn main() -> anyhow::Result<()> {
                         config.height = size.height;
                         surface.configure(&user_data.device, &config);
                         // On macos the window needs to be redrawn manually after resizing
-                        user_data.window.request_redraw();
+                        window.request_redraw();
                     }
                     winit::event::WindowEvent::CloseRequested => {
                         *control_flow = winit::event_loop::ControlFlow::Exit
@@ -702,120 +1258,26 @@ fn main() -> anyhow::Result<()> {
                             .translate(move_vec * delta_time * speed);
 
                         user_data.camera_rig.update(delta_time);
+                        user_data.update(delta_time);
                     }
 
-                    user_data.window.request_redraw();
+                    window.request_redraw();
                 }
                 winit::event::Event::RedrawRequested(_) => {
                     user_data
                         .platform
                         .update_time(start_time.elapsed().as_secs_f64());
 
-                    let back_buffer = rps::ResourceDesc {
-                        ty: rps::ResourceType::IMAGE_2D,
-                        temporal_layers: 1,
-                        flags: Default::default(),
-                        buffer_image: rps::ResourceBufferImageDesc {
-                            image: rps::ResourceImageDesc {
-                                width: config.width,
-                                height: config.height,
-                                mip_levels: 1,
-                                sample_count: 1,
-                                format: map_wgpu_format_to_rps(swapchain_format),
-                                depth_or_array_layers: 1,
-                            },
-                        },
-                    };
-
-                    let kernel_img = rps::ResourceDesc {
-                        ty: rps::ResourceType::IMAGE_2D,
-                        temporal_layers: 1,
-                        flags: Default::default(),
-                        buffer_image: rps::ResourceBufferImageDesc {
-                            image: rps::ResourceImageDesc {
-                                width: kernel.width(),
-                                height: kernel.height(),
-                                mip_levels: 1,
-                                sample_count: 1,
-                                format: map_wgpu_format_to_rps(kernel.format()),
-                                depth_or_array_layers: 1,
-                            },
-                        },
-                    };
-
-                    let args: &[rps::Constant] = &[
-                        (&back_buffer) as *const rps::ResourceDesc as _,
-                        (&kernel_img) as *const rps::ResourceDesc as _,
-                    ];
-
                     let frame = surface
                         .get_current_texture()
                         .expect("Failed to acquire next swap chain texture");
 
-                    let backbuffer_ptr = box_untyped(Resource::SurfaceFrame(
+                    renderer.render(
                         frame.texture.create_view(&Default::default()),
-                    ));
-
-                    let kernel_img_ptr = box_untyped(Resource::SurfaceFrame(
-                        kernel.create_view(&Default::default()),
-                    ));
-
-                    let arg_resources = &[
-                        (&backbuffer_ptr) as *const *mut c_void as _,
-                        (&kernel_img_ptr) as *const *mut c_void as _,
-                    ];
-
-                    let update_info = rps::RenderGraphUpdateInfo {
-                        frame_index,
-                        gpu_completed_frame_index: completed_frame_index,
-                        diagnostic_flags: if first_time {
-                            rps::DiagnosticFlags::empty()
-                        } else {
-                            rps::DiagnosticFlags::empty()
-                        },
-                        num_args: args.len() as u32,
-                        args: args.as_ptr(),
-                        arg_resources: arg_resources.as_ptr(),
-                        ..Default::default()
-                    };
-
-                    first_time = false;
-
-                    rps::render_graph_update(graph, &update_info).unwrap();
-
-                    let layout = rps::render_graph_get_batch_layout(graph).unwrap();
-
-                    for batch in layout.cmd_batches() {
-                        let encoder = user_data.device.create_command_encoder(
-                            &wgpu::CommandEncoderDescriptor { label: None },
-                        );
-
-                        let mut cb = CommandBuffer {
-                            encoder: Some(encoder),
-                        };
-
-                        let cb_ptr = &cb as *const CommandBuffer;
-
-                        rps::render_graph_record_commands(
-                            graph,
-                            &rps::RenderGraphRecordCommandInfo {
-                                user_context: user_data_raw as *mut std::ffi::c_void,
-                                cmd_buffer: rps::RuntimeCommandBuffer::from_raw(cb_ptr as _),
-                                frame_index,
-                                cmd_begin_index: batch.cmd_begin,
-                                num_cmds: batch.num_cmds,
-                                flags: Default::default(),
-                            },
-                        )
-                        .unwrap();
-
-                        let encoder = cb.encoder.take().unwrap();
-
-                        user_data.queue.submit(Some(encoder.finish()));
-                    }
-
-                    completed_frame_index = frame_index;
-                    frame_index += 1;
+                        config.width,
+                        config.height,
+                        swapchain_format,
+                    );
 
                     frame.present();
                 }
@@ -869,17 +1331,41 @@ impl<'a, T> std::ops::Deref for BorrowedOrOwned<'a, T> {
     }
 }
 
-pub fn map_wgpu_format_to_rps(format: wgpu::TextureFormat) -> rps::Format {
-    match format {
-        wgpu::TextureFormat::Bgra8UnormSrgb => rps::Format::B8G8R8A8_UNORM_SRGB,
-        wgpu::TextureFormat::Rgba8UnormSrgb => rps::Format::R8G8B8A8_UNORM_SRGB,
-        other => panic!("{:?}", other),
-    }
+// Covers the surface formats and the formats that KTX2 files can be loaded as, apart from ASTC,
+// which RPS doesn't have.
+pub fn map_wgpu_format_to_rps(format: wgpu::TextureFormat) -> Option<rps::Format> {
+    use wgpu::TextureFormat;
+
+    Some(match format {
+        TextureFormat::Bgra8Unorm => rps::Format::B8G8R8A8_UNORM,
+        TextureFormat::Bgra8UnormSrgb => rps::Format::B8G8R8A8_UNORM_SRGB,
+        TextureFormat::Rgba8Unorm => rps::Format::R8G8B8A8_UNORM,
+        TextureFormat::Rgba8UnormSrgb => rps::Format::R8G8B8A8_UNORM_SRGB,
+        TextureFormat::Rgba16Float => rps::Format::R16G16B16A16_FLOAT,
+        TextureFormat::Rgba32Float => rps::Format::R32G32B32A32_FLOAT,
+        TextureFormat::Rgb9e5Ufloat => rps::Format::R9G9B9E5_SHAREDEXP,
+        TextureFormat::Bc1RgbaUnorm => rps::Format::BC1_UNORM,
+        TextureFormat::Bc1RgbaUnormSrgb => rps::Format::BC1_UNORM_SRGB,
+        TextureFormat::Bc2RgbaUnorm => rps::Format::BC2_UNORM,
+        TextureFormat::Bc2RgbaUnormSrgb => rps::Format::BC2_UNORM_SRGB,
+        TextureFormat::Bc3RgbaUnorm => rps::Format::BC3_UNORM,
+        TextureFormat::Bc3RgbaUnormSrgb => rps::Format::BC3_UNORM_SRGB,
+        TextureFormat::Bc4RUnorm => rps::Format::BC4_UNORM,
+        TextureFormat::Bc4RSnorm => rps::Format::BC4_SNORM,
+        TextureFormat::Bc5RgUnorm => rps::Format::BC5_UNORM,
+        TextureFormat::Bc5RgSnorm => rps::Format::BC5_SNORM,
+        TextureFormat::Bc6hRgbUfloat => rps::Format::BC6H_UF16,
+        TextureFormat::Bc6hRgbFloat => rps::Format::BC6H_SF16,
+        TextureFormat::Bc7RgbaUnorm => rps::Format::BC7_UNORM,
+        TextureFormat::Bc7RgbaUnormSrgb => rps::Format::BC7_UNORM_SRGB,
+        _ => return None,
+    })
 }
 
 pub fn map_rps_format_to_wgpu(format: rps::Format) -> Option<wgpu::TextureFormat> {
     Some(match format {
         rps::Format::B8G8R8A8_UNORM_SRGB => wgpu::TextureFormat::Bgra8UnormSrgb,
+        rps::Format::R8G8B8A8_UNORM_SRGB => wgpu::TextureFormat::Rgba8UnormSrgb,
         rps::Format::R16G16B16A16_FLOAT => wgpu::TextureFormat::Rgba16Float,
         rps::Format::R32G32B32A32_FLOAT => wgpu::TextureFormat::Rgba32Float,
         rps::Format::D32_FLOAT => wgpu::TextureFormat::Depth32Float,
@@ -0,0 +1,160 @@
+use crate::pipelines::{RenderPipeline, ShaderSource};
+use crate::texture_loading::mip_level_count;
+use std::collections::HashMap;
+
+const SUPPORTED_FORMATS: [wgpu::TextureFormat; 3] = [
+    wgpu::TextureFormat::Rgba8Unorm,
+    wgpu::TextureFormat::Rgba8UnormSrgb,
+    wgpu::TextureFormat::Rgba16Float,
+];
+
+// Builds mip chains on the GPU by repeatedly downsampling the previous level. This uses render
+// passes instead of compute as sRGB formats can't be bound as storage textures.
+pub struct MipGenerator {
+    pipelines: HashMap<wgpu::TextureFormat, RenderPipeline>,
+    sampler: wgpu::Sampler,
+}
+
+impl MipGenerator {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            pipelines: SUPPORTED_FORMATS
+                .iter()
+                .map(|&format| {
+                    (
+                        format,
+                        RenderPipeline::new(
+                            device,
+                            &ShaderSource::Hlsl("shaders/generate_mip.hlsl"),
+                            "VSMain",
+                            "PSMain",
+                            &[Some(format.into())],
+                            None,
+                            &[],
+                            &Default::default(),
+                            false,
+                        ),
+                    )
+                })
+                .collect(),
+            sampler: device.create_sampler(&wgpu::SamplerDescriptor {
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn supports(&self, format: wgpu::TextureFormat) -> bool {
+        self.pipelines.contains_key(&format)
+    }
+
+    // Create a 2D texture with a full mip chain from the data for the first level of each layer.
+    // `descriptor.mip_level_count` is ignored.
+    pub fn create_texture_with_mips(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        descriptor: &wgpu::TextureDescriptor,
+        data: &[u8],
+    ) -> wgpu::Texture {
+        let size = descriptor.size;
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            mip_level_count: mip_level_count(size.width, size.height),
+            usage: descriptor.usage
+                | wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST,
+            ..*descriptor
+        });
+
+        let bytes_per_pixel = descriptor.format.block_size(None).unwrap();
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(size.width * bytes_per_pixel),
+                rows_per_image: Some(size.height),
+            },
+            size,
+        );
+
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+
+        self.generate(device, &mut encoder, &texture);
+
+        queue.submit(Some(encoder.finish()));
+
+        texture
+    }
+
+    // Fill in every mip level after the first of each layer of a 2D texture. The texture needs to
+    // have been created with `RENDER_ATTACHMENT` usage.
+    pub fn generate(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) {
+        let pipeline = &self.pipelines[&texture.format()];
+
+        let view = |layer: u32, mip: u32| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_mip_level: mip,
+                mip_level_count: Some(1),
+                base_array_layer: layer,
+                array_layer_count: Some(1),
+                ..Default::default()
+            })
+        };
+
+        for layer in 0..texture.depth_or_array_layers() {
+            for mip in 1..texture.mip_level_count() {
+                let source = view(layer, mip - 1);
+                let dest = view(layer, mip);
+
+                let bind_group = pipeline.bind_group_layouts.create_bind_group(
+                    device,
+                    0,
+                    &mut vec![
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&source),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&self.sampler),
+                        },
+                    ],
+                );
+
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("mip generation"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &dest,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+
+                render_pass.set_pipeline(&pipeline.pipeline);
+                render_pass.set_bind_group(0, &bind_group, &[]);
+                render_pass.draw(0..3, 0..1);
+            }
+        }
+    }
+}
@@ -1,13 +1,16 @@
 use crate::accessors::PrimitiveReader;
-use crate::bindless_textures::BindlessTextures;
+use crate::animation::{AnimatedPrimitive, AnimationState, MorphTargetDelta, MorphTargets};
+use crate::bindless_textures::{BindlessTextures, TextureHandle};
 use crate::buffers;
-use crate::buffers::VecGpuBuffer;
-use crate::texture_loading::load_ktx2;
+use crate::lights::Light;
+use crate::texture_loading::ImageFormat;
+use crate::texture_streaming::{Placeholder, TextureStreamer};
 use base64::Engine;
-use glam::{Vec2, Vec3, Vec4};
+use glam::{Mat4, Quat, UVec4, Vec2, Vec3, Vec4};
 use goth_gltf::default_extensions::Extensions;
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
-use std::collections::HashMap;
+use goth_gltf::extensions::khr_lights_punctual::LightType;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::ops::Range;
 use std::path::{Path, PathBuf};
 use wgpu::util::DeviceExt;
@@ -22,6 +25,39 @@ pub struct MaterialInfo {
     pub albedo_texture: u32,
     pub normal_texture: u32,
     pub emissive_texture: u32,
+    // Which UV set (0 or 1) each texture is sampled with.
+    pub albedo_uv_set: u32,
+    pub normal_uv_set: u32,
+    pub emissive_uv_set: u32,
+    // Roughness in green, metalness in blue.
+    pub metallic_roughness_texture: u32,
+    pub occlusion_texture: u32,
+    pub metallic_roughness_uv_set: u32,
+    pub occlusion_uv_set: u32,
+    pub occlusion_strength: f32,
+}
+
+impl MaterialInfo {
+    // The material that glTF primitives without one are rendered with.
+    fn gltf_default() -> Self {
+        Self {
+            base_color_factor: Vec4::ONE,
+            emissive_factor: Vec3::ZERO,
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            albedo_texture: u32::max_value(),
+            normal_texture: u32::max_value(),
+            emissive_texture: u32::max_value(),
+            albedo_uv_set: 0,
+            normal_uv_set: 0,
+            emissive_uv_set: 0,
+            metallic_roughness_texture: u32::max_value(),
+            occlusion_texture: u32::max_value(),
+            metallic_roughness_uv_set: 0,
+            occlusion_uv_set: 0,
+            occlusion_strength: 1.0,
+        }
+    }
 }
 
 #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
@@ -31,13 +67,74 @@ pub struct Vertex {
     pub uv: Vec2,
 }
 
+// Split a `data:<mime type>;base64,<data>` URI into its MIME type and decoded data.
+fn decode_data_uri(uri: &str) -> anyhow::Result<(&str, Vec<u8>)> {
+    let (header, data) = uri
+        .split_once(',')
+        .ok_or_else(|| anyhow::anyhow!("Failed to get data uri split"))?;
+
+    let mime_type = header
+        .trim_start_matches("data:")
+        .split(';')
+        .next()
+        .unwrap_or_default();
+
+    Ok((
+        mime_type,
+        base64::engine::general_purpose::STANDARD.decode(data)?,
+    ))
+}
+
+// Get the bytes of an image, whether it's in a file, a data URI or a buffer view, along with the
+// format to decode it as.
+fn read_image<'a>(
+    path: &Path,
+    image: &goth_gltf::Image,
+    buffer_view_map: &'a HashMap<usize, Vec<u8>>,
+) -> anyhow::Result<(Cow<'a, [u8]>, ImageFormat)> {
+    let (bytes, format) = match (&image.uri, image.buffer_view) {
+        (Some(uri), _) if uri.starts_with("data:") => {
+            let (mime_type, data) = decode_data_uri(uri)?;
+            let format = ImageFormat::from_mime_type(mime_type);
+            (Cow::Owned(data), format)
+        }
+        (Some(uri), _) => {
+            let mut image_path = PathBuf::from(path);
+            image_path.set_file_name(uri);
+
+            let bytes = std::fs::read(&image_path).map_err(|error| {
+                anyhow::anyhow!("Failed to read {}: {}", image_path.display(), error)
+            })?;
+
+            (Cow::Owned(bytes), ImageFormat::from_extension(&image_path))
+        }
+        (None, Some(buffer_view)) => {
+            let bytes = buffer_view_map.get(&buffer_view).ok_or_else(|| {
+                anyhow::anyhow!("Buffer view index {} is out of range", buffer_view)
+            })?;
+
+            (Cow::Borrowed(&bytes[..]), None)
+        }
+        (None, None) => return Err(anyhow::anyhow!("Image has neither a URI nor a buffer view")),
+    };
+
+    // An explicit MIME type takes priority over the URI, with the file contents as a last resort.
+    let format = image
+        .mime_type
+        .as_deref()
+        .and_then(ImageFormat::from_mime_type)
+        .or(format)
+        .or_else(|| ImageFormat::from_bytes(&bytes))
+        .ok_or_else(|| anyhow::anyhow!("Unsupported image format"))?;
+
+    Ok((bytes, format))
+}
+
 fn collect_buffer_view_map(
     path: &std::path::Path,
     gltf: &goth_gltf::Gltf<Extensions>,
     glb_buffer: Option<&[u8]>,
 ) -> anyhow::Result<HashMap<usize, Vec<u8>>> {
-    use std::borrow::Cow;
-
     let mut buffer_map = HashMap::new();
 
     if let Some(glb_buffer) = glb_buffer {
@@ -61,18 +158,16 @@ fn collect_buffer_view_map(
         };
 
         if uri.starts_with("data") {
-            let (_mime_type, data) = uri
-                .split_once(',')
-                .ok_or_else(|| anyhow::anyhow!("Failed to get data uri split"))?;
+            let (_mime_type, data) = decode_data_uri(uri)?;
             log::warn!("Loading buffers from embedded base64 is inefficient. Consider moving the buffers into a seperate file.");
-            buffer_map.insert(
-                index,
-                Cow::Owned(base64::engine::general_purpose::STANDARD.decode(data)?),
-            );
+            buffer_map.insert(index, Cow::Owned(data));
         } else {
             let mut path = std::path::PathBuf::from(path);
             path.set_file_name(uri);
-            buffer_map.insert(index, Cow::Owned(std::fs::read(&path).unwrap()));
+            let bytes = std::fs::read(&path).map_err(|error| {
+                anyhow::anyhow!("Failed to read buffer {}: {}", path.display(), error)
+            })?;
+            buffer_map.insert(index, Cow::Owned(bytes));
         }
     }
 
@@ -80,199 +175,873 @@ fn collect_buffer_view_map(
 
     for (i, buffer_view) in gltf.buffer_views.iter().enumerate() {
         if let Some(buffer) = buffer_map.get(&buffer_view.buffer) {
-            buffer_view_map.insert(
-                i,
-                buffer[buffer_view.byte_offset..buffer_view.byte_offset + buffer_view.byte_length]
-                    .to_vec(),
-            );
+            let range = buffer_view.byte_offset..buffer_view.byte_offset + buffer_view.byte_length;
+
+            let bytes = buffer.get(range.clone()).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Buffer view {} ({:?}) is out of range of buffer {} ({} bytes)",
+                    i,
+                    range,
+                    buffer_view.buffer,
+                    buffer.len()
+                )
+            })?;
+
+            buffer_view_map.insert(i, bytes.to_vec());
         }
     }
 
     Ok(buffer_view_map)
 }
 
+// Copy the vertex attribute values into a `Vec`, in `order` if there is one. Missing values are
+// filled in with defaults.
+fn reorder<T: Copy + Default>(values: Cow<[T]>, order: Option<&[u32]>) -> Vec<T> {
+    match order {
+        Some(order) => order
+            .iter()
+            .map(|&index| values.get(index as usize).copied().unwrap_or_default())
+            .collect(),
+        None => values.into_owned(),
+    }
+}
+
+// The glTF `texCoord` of a texture, clamped to the two UV sets that are uploaded.
+fn uv_set(tex_coord: usize) -> u32 {
+    tex_coord.min(1) as u32
+}
+
+struct TangentGeometry<'a> {
+    positions: &'a [Vec3],
+    normals: &'a [Vec3],
+    uvs: &'a [Vec2],
+    indices: &'a [u32],
+    tangents: Vec<Vec4>,
+}
+
+impl<'a> TangentGeometry<'a> {
+    fn vertex(&self, face: usize, vert: usize) -> usize {
+        self.indices[face * 3 + vert] as usize
+    }
+}
+
+impl<'a> bevy_mikktspace::Geometry for TangentGeometry<'a> {
+    fn num_faces(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.positions[self.vertex(face, vert)].into()
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.normals[self.vertex(face, vert)].into()
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        self.uvs[self.vertex(face, vert)].into()
+    }
+
+    // MikkTSpace works per face corner, so vertices that are shared by faces with different
+    // tangents end up with whichever is written last.
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        let vertex = self.vertex(face, vert);
+        self.tangents[vertex] = Vec4::from(tangent);
+    }
+}
+
+// Generate MikkTSpace tangents for an indexed triangle list, for normal mapping primitives that
+// don't come with their own.
+fn generate_tangents(
+    positions: &[Vec3],
+    normals: &[Vec3],
+    uvs: &[Vec2],
+    indices: &[u32],
+) -> Vec<Vec4> {
+    let mut geometry = TangentGeometry {
+        positions,
+        normals,
+        uvs,
+        indices,
+        tangents: vec![Vec4::new(1.0, 0.0, 0.0, 1.0); positions.len()],
+    };
+
+    if !bevy_mikktspace::generate_tangents(&mut geometry) {
+        log::warn!("Failed to generate tangents");
+    }
+
+    geometry.tangents
+}
+
+// Calculate a normal for each triangle of an unindexed triangle list.
+fn flat_normals(positions: &[Vec3]) -> Vec<Vec3> {
+    positions
+        .chunks(3)
+        .flat_map(|triangle| {
+            let normal = match *triangle {
+                [a, b, c] => (b - a).cross(c - a).normalize_or_zero(),
+                _ => Vec3::ZERO,
+            };
+
+            std::iter::repeat(normal).take(triangle.len())
+        })
+        .collect()
+}
+
 pub struct Model {
     pub indices: Range<u32>,
+    // Indices are relative to the start of this range, so that it can be moved by compaction
+    // without rewriting them.
     pub vertices: Range<u32>,
-    pub textures: Range<u32>,
+    // A bindless texture slot for each glTF image.
+    pub textures: Vec<TextureHandle>,
     pub material_infos: Range<u32>,
+    // The base pose vertices and morph target deltas of animated meshes. Empty if there aren't
+    // any.
+    pub animated_vertices: Range<u32>,
+    pub morph_target_deltas: Range<u32>,
+    // The index range of each glTF mesh.
+    pub meshes: Vec<Range<u32>>,
+    // Every node in the scene that references a mesh, as a (mesh index, model space transform).
+    pub mesh_instances: Vec<(usize, Mat4)>,
+    // `KHR_lights_punctual` lights in model space.
+    pub lights: Vec<Light>,
+    // `None` unless the model has skinned or morphed meshes.
+    pub animation: Option<AnimationState>,
+}
+
+impl Model {
+    // Update the ranges of a model after the buffers they point into have been compacted.
+    pub fn remap(
+        &mut self,
+        vertices: &buffers::Remapping,
+        animated_vertices: &buffers::Remapping,
+        morph_target_deltas: &buffers::Remapping,
+        indices: &buffers::Remapping,
+    ) {
+        self.vertices = vertices.range(self.vertices.clone());
+        self.animated_vertices = animated_vertices.range(self.animated_vertices.clone());
+        self.morph_target_deltas = morph_target_deltas.range(self.morph_target_deltas.clone());
+        self.indices = indices.range(self.indices.clone());
+
+        for mesh in &mut self.meshes {
+            *mesh = indices.range(mesh.clone());
+        }
+
+        if let Some(animation) = &mut self.animation {
+            for primitive in &mut animation.primitives {
+                primitive.source = animated_vertices.range(primitive.source.clone());
+                primitive.dest_start = vertices.offset(primitive.dest_start);
+
+                if let Some(morph_targets) = &mut primitive.morph_targets {
+                    morph_targets.deltas_start =
+                        morph_target_deltas.offset(morph_targets.deltas_start);
+                }
+            }
+        }
+    }
+}
+
+fn node_transform(node: &goth_gltf::Node<Extensions>) -> Mat4 {
+    match node.matrix {
+        Some(matrix) => Mat4::from_cols_array(&matrix),
+        None => Mat4::from_scale_rotation_translation(
+            Vec3::from(node.scale),
+            Quat::from_array(node.rotation),
+            Vec3::from(node.translation),
+        ),
+    }
+}
+
+// Walk the default scene (or every root node if there isn't one), accumulating node transforms.
+// Nodes that are reached more than once, such as through a cycle of children, are an error.
+fn visit_nodes(
+    gltf: &goth_gltf::Gltf<Extensions>,
+    mut visit: impl FnMut(&goth_gltf::Node<Extensions>, Mat4),
+) -> anyhow::Result<()> {
+    let roots = match gltf.scenes.get(gltf.scene.unwrap_or(0)) {
+        Some(scene) => scene.nodes.clone(),
+        None => {
+            let mut is_child = vec![false; gltf.nodes.len()];
+
+            for node in &gltf.nodes {
+                for &child in &node.children {
+                    if let Some(is_child) = is_child.get_mut(child) {
+                        *is_child = true;
+                    }
+                }
+            }
+
+            (0..gltf.nodes.len()).filter(|&i| !is_child[i]).collect()
+        }
+    };
+
+    let mut stack: Vec<(usize, Mat4)> = roots
+        .into_iter()
+        .map(|node| (node, Mat4::IDENTITY))
+        .collect();
+
+    let mut visited = vec![false; gltf.nodes.len()];
+
+    while let Some((node_index, parent_transform)) = stack.pop() {
+        let node = match gltf.nodes.get(node_index) {
+            Some(node) => node,
+            None => continue,
+        };
+
+        if std::mem::replace(&mut visited[node_index], true) {
+            return Err(anyhow::anyhow!(
+                "Node {} is reached more than once in the node hierarchy",
+                node_index
+            ));
+        }
+
+        let transform = parent_transform * node_transform(node);
+
+        visit(node, transform);
+
+        stack.extend(node.children.iter().map(|&child| (child, transform)));
+    }
+
+    Ok(())
+}
+
+fn collect_mesh_instances(
+    gltf: &goth_gltf::Gltf<Extensions>,
+) -> anyhow::Result<Vec<(usize, Mat4)>> {
+    let mut mesh_instances = Vec::new();
+
+    visit_nodes(gltf, |node, transform| {
+        if let Some(mesh) = node.mesh {
+            // Skinned meshes are positioned by their joints alone.
+            let mesh_transform = match node.skin {
+                Some(_) => Mat4::IDENTITY,
+                None => transform,
+            };
+
+            mesh_instances.push((mesh, mesh_transform));
+        }
+    })?;
+
+    Ok(mesh_instances)
+}
+
+// Place the `KHR_lights_punctual` lights referenced by nodes in model space. Lights point down the
+// -Z axis of their node.
+fn collect_lights(gltf: &goth_gltf::Gltf<Extensions>) -> anyhow::Result<Vec<Light>> {
+    let gltf_lights = match &gltf.extensions.khr_lights_punctual {
+        Some(extension) => &extension.lights,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut lights = Vec::new();
+
+    visit_nodes(gltf, |node, transform| {
+        let light = match node
+            .extensions
+            .khr_lights_punctual
+            .as_ref()
+            .and_then(|extension| gltf_lights.get(extension.light))
+        {
+            Some(light) => light,
+            None => return,
+        };
+
+        let color = Vec3::from(light.color);
+
+        let light = match light.type_ {
+            LightType::Directional => Light::directional(Vec3::NEG_Z, color, light.intensity),
+            LightType::Point => Light::point(Vec3::ZERO, color, light.intensity, light.range),
+            LightType::Spot => {
+                let spot = light.spot.as_ref();
+
+                Light::spot(
+                    Vec3::ZERO,
+                    Vec3::NEG_Z,
+                    color,
+                    light.intensity,
+                    light.range,
+                    spot.map(|spot| spot.inner_cone_angle).unwrap_or(0.0),
+                    spot.map(|spot| spot.outer_cone_angle)
+                        .unwrap_or(std::f32::consts::FRAC_PI_4),
+                )
+            }
+        };
+
+        lights.push(light.transformed(transform));
+    })?;
+
+    Ok(lights)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn load_gltf<P: std::convert::AsRef<std::path::Path> + Sync>(
     path: P,
     vertex_buffers: &buffers::VertexBuffers,
+    animated_vertex_buffers: &buffers::AnimatedVertexBuffers,
+    morph_target_deltas: &buffers::AllocatedBuffer<MorphTargetDelta>,
     index_buffer: &buffers::IndexBuffer,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     bindless_textures: &mut BindlessTextures,
-    material_info_buffer: &mut VecGpuBuffer<MaterialInfo>,
+    material_info_buffer: &buffers::AllocatedBuffer<MaterialInfo>,
+    texture_streamer: &TextureStreamer,
 ) -> anyhow::Result<Model> {
-    let bytes = std::fs::read(path.as_ref()).unwrap();
+    let path = path.as_ref();
 
-    let (gltf, glb_buffer) = goth_gltf::Gltf::from_bytes(&bytes)?;
+    let bytes = std::fs::read(path)
+        .map_err(|error| anyhow::anyhow!("Failed to read {}: {}", path.display(), error))?;
+
+    let (gltf, glb_buffer) = goth_gltf::Gltf::from_bytes(&bytes)
+        .map_err(|error| anyhow::anyhow!("Failed to parse {}: {}", path.display(), error))?;
     //let node_tree = gltf_helpers::NodeTree::new(&gltf);
 
     //let buffer_blob = gltf.blob.as_ref().unwrap();
 
-    let mut buffer_view_map = collect_buffer_view_map(path.as_ref(), &gltf, glb_buffer).unwrap();
+    let buffer_view_map = collect_buffer_view_map(path, &gltf, glb_buffer).map_err(|error| {
+        anyhow::anyhow!("Failed to load buffers of {}: {}", path.display(), error)
+    })?;
+
+    // Walked before anything is allocated, so that a broken node hierarchy doesn't leak.
+    let node_error = |error: anyhow::Error| {
+        anyhow::anyhow!("Failed to read the nodes of {}: {}", path.display(), error)
+    };
+    let mesh_instances = collect_mesh_instances(&gltf).map_err(node_error)?;
+    let lights = collect_lights(&gltf).map_err(node_error)?;
 
-    let texture_views = gltf
+    // Materials refer to glTF textures, which pair an image with a sampler. Several textures can
+    // share an image.
+    let texture_images = gltf
+        .textures
+        .iter()
+        .enumerate()
+        .map(|(index, texture)| {
+            texture
+                .source
+                .filter(|&image| image < gltf.images.len())
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Texture {} of {} doesn't have a valid image",
+                        index,
+                        path.display()
+                    )
+                })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let image_index = |texture: usize| {
+        texture_images.get(texture).copied().ok_or_else(|| {
+            anyhow::anyhow!(
+                "Texture index {} of {} out of bounds",
+                texture,
+                path.display()
+            )
+        })
+    };
+
+    // Color textures are sRGB while everything else is linear.
+    let srgb_images: HashSet<usize> = gltf
+        .materials
+        .iter()
+        .flat_map(|material| {
+            [
+                material.pbr_metallic_roughness.base_color_texture.as_ref(),
+                material.emissive_texture.as_ref(),
+            ]
+        })
+        .flatten()
+        .map(|info| image_index(info.index))
+        .collect::<anyhow::Result<_>>()?;
+
+    let normal_images: HashSet<usize> = gltf
+        .materials
+        .iter()
+        .filter_map(|material| material.normal_texture.as_ref())
+        .map(|info| image_index(info.index))
+        .collect::<anyhow::Result<_>>()?;
+
+    let emissive_images: HashSet<usize> = gltf
+        .materials
+        .iter()
+        .filter_map(|material| material.emissive_texture.as_ref())
+        .map(|info| image_index(info.index))
+        .collect::<anyhow::Result<_>>()?;
+
+    let placeholder = |index: usize| {
+        if normal_images.contains(&index) {
+            Placeholder::FlatNormal
+        } else if emissive_images.contains(&index) {
+            Placeholder::Black
+        } else {
+            Placeholder::White
+        }
+    };
+
+    // The images are read up front so that any errors are reported immediately, but they're
+    // decoded and uploaded in the background.
+    let images = gltf
         .images
-        .par_iter()
-        .map(|image| {
-            if let Some(uri) = &image.uri {
-                let mut path = PathBuf::from(path.as_ref());
-                path.set_file_name(uri);
-                load_ktx2(&std::fs::read(&path).unwrap(), device, queue)
-                    .create_view(&Default::default())
-            } else {
-                panic!()
-            }
+        .iter()
+        .enumerate()
+        .map(|(index, image)| {
+            read_image(path, image, &buffer_view_map)
+                .map(|(bytes, format)| (bytes.into_owned(), format))
+                .map_err(|error| {
+                    anyhow::anyhow!(
+                        "Failed to read image {} of {}: {}",
+                        index,
+                        path.display(),
+                        error
+                    )
+                })
         })
-        .collect::<Vec<_>>();
+        .collect::<anyhow::Result<Vec<_>>>()?;
 
-    let textures_range = bindless_textures.push(texture_views);
+    let textures = (0..images.len())
+        .map(|index| {
+            bindless_textures.insert(texture_streamer.placeholder_view(placeholder(index)))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
 
-    let first_texture = textures_range.start;
+    for (index, (bytes, format)) in images.into_iter().enumerate() {
+        texture_streamer.load(
+            textures[index],
+            format!("image {} of {}", index, path.display()),
+            bytes,
+            format,
+            srgb_images.contains(&index),
+        );
+    }
+
+    // The index of a texture's image in the bindless array, or `u32::MAX` if there isn't one.
+    let bindless_index = |texture: Option<usize>| match texture {
+        Some(texture) => image_index(texture).map(|image| textures[image].index()),
+        None => Ok(u32::max_value()),
+    };
 
     let mut material_infos = Vec::new();
 
     for material in &gltf.materials {
         material_infos.push(MaterialInfo {
             base_color_factor: material.pbr_metallic_roughness.base_color_factor.into(),
-            albedo_texture: material
-                .pbr_metallic_roughness
-                .base_color_texture
-                .as_ref()
-                .map(|info| first_texture + info.index as u32)
-                .unwrap_or(u32::max_value()),
+            albedo_texture: bindless_index(
+                material
+                    .pbr_metallic_roughness
+                    .base_color_texture
+                    .as_ref()
+                    .map(|info| info.index),
+            )?,
             metallic_factor: material.pbr_metallic_roughness.metallic_factor,
             roughness_factor: material.pbr_metallic_roughness.roughness_factor,
-            normal_texture: material
-                .normal_texture
-                .as_ref()
-                .map(|info| first_texture + info.index as u32)
-                .unwrap_or(u32::max_value()),
+            normal_texture: bindless_index(
+                material.normal_texture.as_ref().map(|info| info.index),
+            )?,
             emissive_factor: Vec3::from(material.emissive_factor)
                 * material
                     .extensions
                     .khr_materials_emissive_strength
                     .map(|ext| ext.emissive_strength)
                     .unwrap_or(1.0),
-            emissive_texture: material
+            emissive_texture: bindless_index(
+                material.emissive_texture.as_ref().map(|info| info.index),
+            )?,
+            albedo_uv_set: material
+                .pbr_metallic_roughness
+                .base_color_texture
+                .as_ref()
+                .map(|info| uv_set(info.tex_coord))
+                .unwrap_or(0),
+            normal_uv_set: material
+                .normal_texture
+                .as_ref()
+                .map(|info| uv_set(info.tex_coord))
+                .unwrap_or(0),
+            emissive_uv_set: material
                 .emissive_texture
                 .as_ref()
-                .map(|info| first_texture + info.index as u32)
-                .unwrap_or(u32::max_value()),
+                .map(|info| uv_set(info.tex_coord))
+                .unwrap_or(0),
+            metallic_roughness_texture: bindless_index(
+                material
+                    .pbr_metallic_roughness
+                    .metallic_roughness_texture
+                    .as_ref()
+                    .map(|info| info.index),
+            )?,
+            occlusion_texture: bindless_index(
+                material.occlusion_texture.as_ref().map(|info| info.index),
+            )?,
+            metallic_roughness_uv_set: material
+                .pbr_metallic_roughness
+                .metallic_roughness_texture
+                .as_ref()
+                .map(|info| uv_set(info.tex_coord))
+                .unwrap_or(0),
+            occlusion_uv_set: material
+                .occlusion_texture
+                .as_ref()
+                .map(|info| uv_set(info.tex_coord))
+                .unwrap_or(0),
+            occlusion_strength: material
+                .occlusion_texture
+                .as_ref()
+                .map(|info| info.strength)
+                .unwrap_or(1.0),
         });
     }
 
+    // Only added if a primitive needs it, after the file's own materials.
+    let default_material = material_infos.len();
+
+    if gltf
+        .meshes
+        .iter()
+        .flat_map(|mesh| &mesh.primitives)
+        .any(|primitive| primitive.material.is_none())
+    {
+        material_infos.push(MaterialInfo::gltf_default());
+    }
+
     let mut encoder =
         device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
-    let material_info_range =
-        material_info_buffer.push(&material_infos, &device, &queue, &mut encoder);
+    let (material_info_range, _) =
+        material_info_buffer.insert(&material_infos, device, queue, &mut encoder);
 
     let mut indices = Vec::new();
     let mut positions = Vec::new();
     let mut normals = Vec::new();
     let mut uvs = Vec::new();
+    let mut second_uvs = Vec::new();
+    let mut tangents = Vec::new();
+    let mut colors = Vec::new();
     let mut material_ids = Vec::new();
+    let mut meshes = Vec::new();
+
+    // The skin that each skinned mesh is attached to.
+    let mesh_skins: HashMap<usize, usize> = gltf
+        .nodes
+        .iter()
+        .filter_map(|node| Some((node.mesh?, node.skin?)))
+        .collect();
 
-    for mesh in &gltf.meshes {
-        for primitive in &mesh.primitives {
-            let material_id = primitive.material.unwrap_or(0);
+    let mut animated_primitives = Vec::new();
+    let mut deltas = Vec::new();
+    let mut animated_positions = Vec::new();
+    let mut animated_normals = Vec::new();
+    let mut animated_uvs = Vec::new();
+    let mut animated_tangents = Vec::new();
+    let mut joint_indices = Vec::new();
+    let mut joint_weights = Vec::new();
 
+    for (mesh_index, mesh) in gltf.meshes.iter().enumerate() {
+        let mesh_start = indices.len() as u32;
+
+        for (primitive_index, primitive) in mesh.primitives.iter().enumerate() {
             let reader = PrimitiveReader::new(&gltf, primitive, &buffer_view_map);
 
-            let read_indices = reader.read_indices().unwrap().unwrap();
+            let context = |error: anyhow::Error| {
+                anyhow::anyhow!(
+                    "Failed to read primitive {} of mesh {} in {}: {}",
+                    primitive_index,
+                    mesh_index,
+                    path.display(),
+                    error
+                )
+            };
 
-            let num_vertices = positions.len() as u32;
+            let material_id = match primitive.material {
+                Some(material) if material < gltf.materials.len() => material,
+                Some(material) => {
+                    return Err(context(anyhow::anyhow!(
+                        "Material index {} out of bounds",
+                        material
+                    )))
+                }
+                None => default_material,
+            };
+
+            let prim_positions = reader
+                .read_positions()
+                .map_err(context)?
+                .ok_or_else(|| context(anyhow::anyhow!("Missing POSITION attribute")))?;
+
+            let mut prim_indices = match reader.read_indices().map_err(context)? {
+                Some(indices) => indices.into_owned(),
+                None => (0..prim_positions.len() as u32).collect(),
+            };
+
+            if let Some(&index) = prim_indices
+                .iter()
+                .find(|&&index| index as usize >= prim_positions.len())
+            {
+                return Err(context(anyhow::anyhow!(
+                    "Index {} is out of range of {} vertices",
+                    index,
+                    prim_positions.len()
+                )));
+            }
 
-            indices.extend(read_indices.iter().map(|index| index + num_vertices));
+            let prim_normals = reader.read_normals().map_err(context)?;
 
-            let prim_positions = reader.read_positions().unwrap().unwrap();
+            // Flat normals need every triangle to have its own vertices, so without normals the
+            // primitive is unindexed and the vertices are read in index order.
+            let vertex_order = match prim_normals {
+                Some(_) => None,
+                None => {
+                    let sequential_indices = (0..prim_indices.len() as u32).collect();
+                    Some(std::mem::replace(&mut prim_indices, sequential_indices))
+                }
+            };
+            let vertex_order = vertex_order.as_deref();
+
+            let prim_positions = reorder(prim_positions, vertex_order);
+
+            let prim_normals = match prim_normals {
+                Some(normals) => reorder(normals, vertex_order),
+                None => flat_normals(&prim_positions),
+            };
+
+            let prim_uvs = match reader.read_uvs().map_err(context)? {
+                Some(uvs) => reorder(uvs, vertex_order),
+                None => vec![Vec2::ZERO; prim_positions.len()],
+            };
+
+            let prim_second_uvs = match reader.read_second_uvs().map_err(context)? {
+                Some(uvs) => reorder(uvs, vertex_order),
+                None => prim_uvs.clone(),
+            };
+
+            let prim_colors = match reader.read_colors().map_err(context)? {
+                Some(colors) => reorder(colors, vertex_order),
+                None => vec![Vec4::ONE; prim_positions.len()],
+            };
+
+            let material_info = &material_infos[material_id];
+
+            let prim_tangents = match reader.read_tangents().map_err(context)? {
+                Some(tangents) => reorder(tangents, vertex_order),
+                // Tangents are only used for normal mapping, so don't bother generating them
+                // otherwise.
+                None if material_info.normal_texture == u32::max_value() => {
+                    vec![Vec4::new(1.0, 0.0, 0.0, 1.0); prim_positions.len()]
+                }
+                None => {
+                    // Tangents have to follow the UVs that the normal map is sampled with.
+                    let normal_uv_set = material_info.normal_uv_set;
+
+                    generate_tangents(
+                        &prim_positions,
+                        &prim_normals,
+                        if normal_uv_set == 1 {
+                            &prim_second_uvs
+                        } else {
+                            &prim_uvs
+                        },
+                        &prim_indices,
+                    )
+                }
+            };
+
+            let num_vertices = positions.len() as u32;
+
+            indices.extend(prim_indices.iter().map(|index| index + num_vertices));
 
             positions.extend_from_slice(&prim_positions);
-            uvs.extend_from_slice(&reader.read_uvs().unwrap().unwrap());
-            normals.extend_from_slice(&reader.read_normals().unwrap().unwrap());
+            uvs.extend_from_slice(&prim_uvs);
+            second_uvs.extend_from_slice(&prim_second_uvs);
+            normals.extend_from_slice(&prim_normals);
+            tangents.extend_from_slice(&prim_tangents);
+            colors.extend_from_slice(&prim_colors);
             material_ids.extend(
                 std::iter::repeat(material_info_range.start + material_id as u32)
                     .take(prim_positions.len()),
             );
+
+            let skinning = match (
+                mesh_skins.get(&mesh_index),
+                reader.read_joints().map_err(context)?,
+                reader.read_weights().map_err(context)?,
+            ) {
+                (Some(&skin), Some(joints), Some(weights)) => Some((skin, joints, weights)),
+                _ => None,
+            };
+
+            if skinning.is_none() && primitive.targets.is_empty() {
+                continue;
+            }
+
+            let source_start = animated_positions.len() as u32;
+
+            animated_positions.extend_from_slice(&prim_positions);
+            animated_normals.extend_from_slice(&prim_normals);
+            animated_uvs.extend_from_slice(&prim_uvs);
+            animated_tangents.extend_from_slice(&prim_tangents);
+
+            let skin = match skinning {
+                Some((skin, prim_joints, prim_weights)) => {
+                    joint_indices.extend(reorder(prim_joints, vertex_order));
+                    joint_weights.extend(reorder(prim_weights, vertex_order));
+                    Some(skin)
+                }
+                None => {
+                    joint_indices.extend(std::iter::repeat(UVec4::ZERO).take(prim_positions.len()));
+                    joint_weights.extend(std::iter::repeat(Vec4::ZERO).take(prim_positions.len()));
+                    None
+                }
+            };
+
+            let morph_targets = if primitive.targets.is_empty() {
+                None
+            } else {
+                let deltas_start = deltas.len() as u32;
+
+                for target in 0..primitive.targets.len() {
+                    let target_positions = reader
+                        .read_morph_target_positions(target)
+                        .map_err(context)?
+                        .map(|positions| reorder(positions, vertex_order));
+                    let target_normals = reader
+                        .read_morph_target_normals(target)
+                        .map_err(context)?
+                        .map(|normals| reorder(normals, vertex_order));
+
+                    deltas.extend((0..prim_positions.len()).map(|i| {
+                        MorphTargetDelta {
+                            position: target_positions
+                                .as_ref()
+                                .and_then(|positions| positions.get(i).copied())
+                                .unwrap_or_default(),
+                            normal: target_normals
+                                .as_ref()
+                                .and_then(|normals| normals.get(i).copied())
+                                .unwrap_or_default(),
+                        }
+                    }));
+                }
+
+                Some(MorphTargets {
+                    deltas_start,
+                    count: primitive.targets.len() as u32,
+                    mesh: mesh_index,
+                })
+            };
+
+            animated_primitives.push(AnimatedPrimitive {
+                source: source_start..animated_positions.len() as u32,
+                dest_start: num_vertices,
+                skin,
+                morph_targets,
+            });
         }
+
+        meshes.push(mesh_start..indices.len() as u32);
     }
 
     let vertex_range = vertex_buffers.insert(
-        &positions,
-        &normals,
-        &uvs,
-        &material_ids,
+        buffers::VertexData {
+            position: &positions,
+            normal: &normals,
+            uv: &uvs,
+            second_uv: &second_uvs,
+            tangent: &tangents,
+            color: &colors,
+            material_id: &material_ids,
+        },
         device,
         queue,
         &mut encoder,
     );
-    for mut index in &mut indices {
-        *index += vertex_range.start;
-    }
+
+    let mut animated_vertices = 0..0;
+    let mut morph_target_deltas_range = 0..0;
+
+    let animation = if animated_primitives.is_empty() {
+        None
+    } else {
+        let animated_range = animated_vertex_buffers.insert(
+            buffers::AnimatedVertexData {
+                position: &animated_positions,
+                normal: &animated_normals,
+                uv: &animated_uvs,
+                tangent: &animated_tangents,
+                joint_indices: &joint_indices,
+                joint_weights: &joint_weights,
+            },
+            device,
+            queue,
+            &mut encoder,
+        );
+
+        let (deltas_range, _) = morph_target_deltas.insert(&deltas, device, queue, &mut encoder);
+        let deltas_start = deltas_range.start;
+
+        for primitive in &mut animated_primitives {
+            primitive.source.start += animated_range.start;
+            primitive.source.end += animated_range.start;
+            primitive.dest_start += vertex_range.start;
+
+            if let Some(morph_targets) = &mut primitive.morph_targets {
+                morph_targets.deltas_start += deltas_start;
+            }
+        }
+
+        animated_vertices = animated_range;
+        morph_target_deltas_range = deltas_range;
+
+        Some(AnimationState::new(
+            &gltf,
+            &buffer_view_map,
+            animated_primitives,
+        )?)
+    };
 
     let index_range = index_buffer.insert(&indices, device, queue, &mut encoder);
 
+    for mesh in &mut meshes {
+        mesh.start += index_range.start;
+        mesh.end += index_range.start;
+    }
+
     queue.submit(Some(encoder.finish()));
 
     Ok(Model {
         indices: index_range,
         vertices: vertex_range,
-        textures: textures_range,
+        textures,
         material_infos: material_info_range,
+        animated_vertices,
+        morph_target_deltas: morph_target_deltas_range,
+        meshes,
+        mesh_instances,
+        lights,
+        animation,
     })
 }
 
-/*
-fn load_texture_from_gltf(
-    texture: gltf::texture::Texture,
-    label: &str,
-    buffer_blob: &[u8],
-    device: &wgpu::Device,
-    queue: &wgpu::Queue,
-) -> anyhow::Result<wgpu::TextureView> {
-    let texture_view = match texture.source().source() {
-        gltf::image::Source::View { view, .. } => view,
-        _ => {
-            return Err(anyhow::anyhow!(
-                "Image source is a uri which we don't support"
-            ))
-        }
-    };
-
-    let texture_start = texture_view.offset();
-    let texture_end = texture_start + texture_view.length();
-    let texture_bytes = &buffer_blob[texture_start..texture_end];
-
-    let decoded_bytes =
-        image::load_from_memory_with_format(texture_bytes, image::ImageFormat::Png)?;
-
-    let decoded_rgba8 = decoded_bytes.to_rgba8();
+// Free everything a model allocated in `load_gltf`, so that the space can be reused by models that
+// are loaded later. Textures that are still streaming in are dropped once they finish.
+#[allow(clippy::too_many_arguments)]
+pub fn unload_model(
+    model: Model,
+    vertex_buffers: &buffers::VertexBuffers,
+    animated_vertex_buffers: &buffers::AnimatedVertexBuffers,
+    morph_target_deltas: &buffers::AllocatedBuffer<MorphTargetDelta>,
+    index_buffer: &buffers::IndexBuffer,
+    bindless_textures: &mut BindlessTextures,
+    material_info_buffer: &buffers::AllocatedBuffer<MaterialInfo>,
+) {
+    vertex_buffers.remove(model.vertices);
+    animated_vertex_buffers.remove(model.animated_vertices);
+    morph_target_deltas.remove(model.morph_target_deltas);
+    index_buffer.remove(model.indices);
+    material_info_buffer.remove(model.material_infos);
 
-    Ok(device
-        .create_texture_with_data(
-            queue,
-            &wgpu::TextureDescriptor {
-                label: Some(label),
-                size: wgpu::Extent3d {
-                    width: decoded_rgba8.width(),
-                    height: decoded_rgba8.height(),
-                    depth_or_array_layers: 1,
-                },
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING,
-                view_formats: &[],
-            },
-            &*decoded_rgba8,
-        )
-        .create_view(&Default::default()))
+    for handle in model.textures {
+        bindless_textures.remove(handle);
+    }
 }
-*/
@@ -1,10 +1,13 @@
 use crate::bindless_textures::BindlessTextures;
+use crate::ibl;
+use crate::lights;
+use crate::shadows;
 use crate::{
     load_texture_view, BorrowedOrOwned, CommandBuffer, ComputePipeline, RenderPipeline, Resource,
     UserData,
 };
 use egui_wgpu_backend::ScreenDescriptor;
-use glam::{Mat4, Vec3};
+use glam::Mat4;
 use rps_custom_backend::{ffi, rps, CmdCallbackContext};
 use wgpu::util::DeviceExt;
 
@@ -67,29 +70,111 @@ pub unsafe extern "C" fn draw(context: *const rps::CmdCallbackContext) {
     let (view, img_desc) = load_texture_view(&context, image_view);
     let (depth_view, _) = load_texture_view(&context, depth_view);
 
-    let moon = &context.user_data.moon;
+    // Only recreate the bind group when a texture has been swapped in or the material or light
+    // buffers have been reallocated.
+    let bind_group_key = (
+        context.user_data.bindless_textures.version(),
+        context.user_data.model_info_buffer.version(),
+        context.user_data.lights.buffer().version(),
+    );
+
+    let moon_bind_group = context
+        .user_data
+        .moon_bind_group
+        .get_or_create(bind_group_key, || {
+            pipeline.bind_group_layouts.create_bind_group(
+                &context.user_data.device,
+                0,
+                &mut vec![
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureViewArray(
+                            &context.user_data.bindless_textures.texture_view_array(),
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&context.user_data.repeat_sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: context
+                            .user_data
+                            .model_info_buffer
+                            .buffer
+                            .load()
+                            .as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(
+                            &context.user_data.ibl.specular_view(),
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::TextureView(
+                            &context.user_data.ibl.irradiance_view(),
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: wgpu::BindingResource::TextureView(
+                            &context.user_data.ibl.brdf_lut_view(),
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 6,
+                        resource: wgpu::BindingResource::Sampler(&context.user_data.ibl.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 7,
+                        resource: context.user_data.lights.buffer().buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 8,
+                        resource: wgpu::BindingResource::TextureView(
+                            &context.user_data.shadows.array_view(),
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 9,
+                        resource: wgpu::BindingResource::Sampler(
+                            &context.user_data.shadows.sampler,
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 10,
+                        resource: context.user_data.shadows.uniforms.as_entire_binding(),
+                    },
+                ],
+            )
+        });
+
+    // Skip drawing rather than panicking if the graph doesn't run `cull_lights` first.
+    let clusters = match context.user_data.lights.clusters() {
+        Some(clusters) => clusters,
+        None => {
+            log::error!("`cull_lights` needs to run before `draw`, skipping the draw");
+            return;
+        }
+    };
 
-    let moon_bind_group = pipeline.bind_group_layouts.create_bind_group(
+    let cluster_bind_group = pipeline.bind_group_layouts.create_bind_group(
         &context.user_data.device,
-        0,
+        1,
         &mut vec![
             wgpu::BindGroupEntry {
                 binding: 0,
-                resource: wgpu::BindingResource::TextureViewArray(
-                    &context.user_data.bindless_textures.texture_view_array(),
-                ),
+                resource: clusters.uniforms.as_entire_binding(),
             },
             wgpu::BindGroupEntry {
                 binding: 1,
-                resource: wgpu::BindingResource::Sampler(&context.user_data.repeat_sampler),
+                resource: clusters.light_counts.as_entire_binding(),
             },
             wgpu::BindGroupEntry {
                 binding: 2,
-                resource: context
-                    .user_data
-                    .model_info_buffer
-                    .buffer
-                    .as_entire_binding(),
+                resource: clusters.light_indices.as_entire_binding(),
             },
         ],
     );
@@ -97,36 +182,12 @@ pub unsafe extern "C" fn draw(context: *const rps::CmdCallbackContext) {
     let vertex_buffers = context.user_data.vertex_buffers.buffers.load();
     let index_buffer = &context.user_data.index_buffer.buffer();
 
-    #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
-    #[repr(C)]
-    struct Instance {
-        pub transform: Vec3,
-        pub scale: f32,
-        pub rotation: glam::Quat,
-    }
-
-    context.user_data.rotation += 1.0 / 60.0;
-
-    let instance_buffer =
-        context
-            .user_data
-            .device
-            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: None,
-                contents: bytemuck::cast_slice(&[
-                    Instance {
-                        transform: Vec3::splat(0.0),
-                        scale: 1.0,
-                        rotation: glam::Quat::from_rotation_y(context.user_data.rotation),
-                    },
-                    Instance {
-                        transform: Vec3::splat(2.0),
-                        scale: 1.0,
-                        rotation: Default::default(),
-                    },
-                ]),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
+    context.user_data.instances.upload(
+        &context.user_data.models,
+        &context.user_data.device,
+        &context.user_data.queue,
+        context.command_buffer.encoder.as_mut().unwrap(),
+    );
 
     let mut render_pass = context
         .command_buffer
@@ -154,42 +215,588 @@ pub unsafe extern "C" fn draw(context: *const rps::CmdCallbackContext) {
         });
 
     let camera_rig = &context.user_data.camera_rig;
+    let (view_matrix, perspective_matrix) =
+        camera_matrices(camera_rig, img_desc.width, img_desc.height);
 
-    let view_matrix = Mat4::look_at_rh(
-        camera_rig.final_transform.position,
-        camera_rig.final_transform.position + camera_rig.final_transform.forward(),
-        camera_rig.final_transform.up(),
-    );
-
-    let perspective_matrix = Mat4::perspective_infinite_reverse_rh(
-        59.0_f32.to_radians(),
-        img_desc.width as f32 / img_desc.height as f32,
-        0.001,
-    );
-
-    let mut bytes = [0; 76];
+    // The environment lighting is scaled by the same amount as the skybox so that the two match.
+    let mut bytes = [0; 80];
     bytes[..64].copy_from_slice(&bytemuck::bytes_of(&(perspective_matrix * view_matrix)));
-    bytes[64..].copy_from_slice(&bytemuck::bytes_of(&camera_rig.final_transform.position));
+    bytes[64..76].copy_from_slice(&bytemuck::bytes_of(&camera_rig.final_transform.position));
+    bytes[76..].copy_from_slice(&bytemuck::bytes_of(&context.user_data.skybox_boost));
 
     render_pass.set_pipeline(&pipeline.pipeline);
     render_pass.set_vertex_buffer(0, vertex_buffers.position.slice(..));
     render_pass.set_vertex_buffer(1, vertex_buffers.uv.slice(..));
     render_pass.set_vertex_buffer(2, vertex_buffers.normal.slice(..));
     render_pass.set_vertex_buffer(3, vertex_buffers.material_id.slice(..));
-    render_pass.set_vertex_buffer(4, instance_buffer.slice(..));
+    render_pass.set_vertex_buffer(4, context.user_data.instances.buffer().slice(..));
+    render_pass.set_vertex_buffer(5, vertex_buffers.second_uv.slice(..));
+    render_pass.set_vertex_buffer(6, vertex_buffers.tangent.slice(..));
+    render_pass.set_vertex_buffer(7, vertex_buffers.color.slice(..));
     render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-    render_pass.set_bind_group(0, &moon_bind_group, &[]);
+    render_pass.set_bind_group(0, moon_bind_group, &[]);
+    render_pass.set_bind_group(1, &cluster_bind_group, &[]);
     render_pass.set_push_constants(
         wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
         0,
         &bytes,
     );
-    render_pass.draw_indexed(moon.indices.clone(), 0, 0..1);
-    render_pass.draw_indexed(context.user_data.bloom.indices.clone(), 0, 1..2);
+
+    for draw in context.user_data.instances.draws() {
+        render_pass.draw_indexed(
+            draw.indices.clone(),
+            draw.base_vertex,
+            draw.instances.clone(),
+        );
+    }
 
     //render_pass.draw(0..3, 0..1);
 }
 
+// Render the depth of every model, so that `cull_lights` knows where the visible surfaces are
+// before `draw` runs.
+pub unsafe extern "C" fn depth_prepass(context: *const rps::CmdCallbackContext) {
+    let context = CmdCallbackContext::<CommandBuffer, UserData, RenderPipeline>::new(context);
+    let pipeline = &context.command_data;
+
+    let depth_view = *(context.args[0] as *const rps::ImageView);
+    let (depth_view, depth_desc) = load_texture_view(&context, depth_view);
+
+    context.user_data.instances.upload(
+        &context.user_data.models,
+        &context.user_data.device,
+        &context.user_data.queue,
+        context.command_buffer.encoder.as_mut().unwrap(),
+    );
+
+    let (view_matrix, perspective_matrix) = camera_matrices(
+        &context.user_data.camera_rig,
+        depth_desc.width,
+        depth_desc.height,
+    );
+
+    let vertex_buffers = context.user_data.vertex_buffers.buffers.load();
+    let index_buffer = &context.user_data.index_buffer.buffer();
+
+    let mut render_pass = context
+        .command_buffer
+        .encoder
+        .as_mut()
+        .unwrap()
+        .begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+    render_pass.set_pipeline(&pipeline.pipeline);
+    render_pass.set_vertex_buffer(0, vertex_buffers.position.slice(..));
+    render_pass.set_vertex_buffer(1, context.user_data.instances.buffer().slice(..));
+    render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+    render_pass.set_push_constants(
+        wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+        0,
+        bytemuck::bytes_of(&(perspective_matrix * view_matrix)),
+    );
+
+    for draw in context.user_data.instances.draws() {
+        render_pass.draw_indexed(
+            draw.indices.clone(),
+            draw.base_vertex,
+            draw.instances.clone(),
+        );
+    }
+}
+
+// Render the models into a shadow map cascade for each slice of the camera frustum, as seen from
+// the first directional light in the light buffer, whether it comes from the scene, the UI or a
+// model. The width and height of the render target are given so that the cascades can be fitted
+// to the camera's aspect ratio.
+pub unsafe extern "C" fn render_shadows(context: *const rps::CmdCallbackContext) {
+    let context = CmdCallbackContext::<CommandBuffer, UserData, RenderPipeline>::new(context);
+    let pipeline = &context.command_data;
+
+    let width = *(context.args[0] as *const u32);
+    let height = *(context.args[1] as *const u32);
+
+    let shadow_light = context
+        .user_data
+        .lights
+        .first_directional(&context.user_data.models, &context.user_data.instances);
+
+    let (light_index, light) = match shadow_light {
+        Some(shadow_light) => shadow_light,
+        None => {
+            context.user_data.queue.write_buffer(
+                &context.user_data.shadows.uniforms,
+                0,
+                bytemuck::bytes_of(&shadows::ShadowUniforms::disabled()),
+            );
+            return;
+        }
+    };
+
+    context.user_data.instances.upload(
+        &context.user_data.models,
+        &context.user_data.device,
+        &context.user_data.queue,
+        context.command_buffer.encoder.as_mut().unwrap(),
+    );
+
+    let (view_matrix, _) = camera_matrices(&context.user_data.camera_rig, width, height);
+
+    let uniforms = context.user_data.shadows.uniforms(
+        view_matrix,
+        CAMERA_FOV_Y,
+        width as f32 / height.max(1) as f32,
+        light.direction,
+        light_index,
+    );
+
+    context.user_data.queue.write_buffer(
+        &context.user_data.shadows.uniforms,
+        0,
+        bytemuck::bytes_of(&uniforms),
+    );
+
+    let vertex_buffers = context.user_data.vertex_buffers.buffers.load();
+    let index_buffer = &context.user_data.index_buffer.buffer();
+
+    for (cascade, cascade_matrix) in uniforms.cascade_matrices.iter().enumerate() {
+        let cascade_view = context.user_data.shadows.cascade_view(cascade as u32);
+
+        let mut render_pass = context
+            .command_buffer
+            .encoder
+            .as_mut()
+            .unwrap()
+            .begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &cascade_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+        render_pass.set_pipeline(&pipeline.pipeline);
+        render_pass.set_vertex_buffer(0, vertex_buffers.position.slice(..));
+        render_pass.set_vertex_buffer(1, context.user_data.instances.buffer().slice(..));
+        render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.set_push_constants(
+            wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            0,
+            bytemuck::bytes_of(cascade_matrix),
+        );
+
+        for draw in context.user_data.instances.draws() {
+            render_pass.draw_indexed(
+                draw.indices.clone(),
+                draw.base_vertex,
+                draw.instances.clone(),
+            );
+        }
+    }
+}
+
+// Upload the lights and sort them into a grid of view space clusters, with the number of clusters
+// along each axis given as node arguments. The depth buffer is used to shrink each cluster to the
+// surfaces inside it.
+pub unsafe extern "C" fn cull_lights(context: *const rps::CmdCallbackContext) {
+    let context = CmdCallbackContext::<CommandBuffer, UserData, ComputePipeline>::new(context);
+    let pipeline = &context.command_data;
+
+    let depth_view = *(context.args[0] as *const rps::ImageView);
+    let dimensions = glam::UVec3::new(
+        *(context.args[1] as *const u32),
+        *(context.args[2] as *const u32),
+        *(context.args[3] as *const u32),
+    )
+    .max(glam::UVec3::ONE);
+
+    let (depth, depth_desc) = load_texture_view(&context, depth_view);
+
+    let light_count = context.user_data.lights.upload(
+        &context.user_data.models,
+        &context.user_data.instances,
+        &context.user_data.device,
+        &context.user_data.queue,
+        context.command_buffer.encoder.as_mut().unwrap(),
+    );
+
+    let (view_matrix, perspective_matrix) = camera_matrices(
+        &context.user_data.camera_rig,
+        depth_desc.width,
+        depth_desc.height,
+    );
+
+    let uniforms = lights::ClusterUniforms {
+        view: view_matrix,
+        projection_scale: glam::Vec2::new(perspective_matrix.x_axis.x, perspective_matrix.y_axis.y),
+        projection_near: CAMERA_NEAR,
+        light_count,
+        dimensions,
+        z_near: lights::CLUSTER_Z_NEAR,
+        tile_size: glam::Vec2::new(
+            depth_desc.width as f32 / dimensions.x as f32,
+            depth_desc.height as f32 / dimensions.y as f32,
+        ),
+        z_far: lights::CLUSTER_Z_FAR,
+        _padding: 0,
+    };
+
+    context
+        .user_data
+        .lights
+        .resize_clusters(dimensions, &context.user_data.device);
+
+    let clusters = context.user_data.lights.clusters().unwrap();
+
+    context
+        .user_data
+        .queue
+        .write_buffer(&clusters.uniforms, 0, bytemuck::bytes_of(&uniforms));
+
+    let bind_group = pipeline.bind_group_layouts.create_bind_group(
+        &context.user_data.device,
+        0,
+        &mut vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&depth),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: clusters.uniforms.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: context.user_data.lights.buffer().buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: clusters.light_counts.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: clusters.light_indices.as_entire_binding(),
+            },
+        ],
+    );
+
+    let mut compute_pass = context
+        .command_buffer
+        .encoder
+        .as_mut()
+        .unwrap()
+        .begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+
+    compute_pass.set_pipeline(&pipeline.pipeline);
+    compute_pass.set_bind_group(0, &bind_group, &[]);
+    // One workgroup per screen tile.
+    compute_pass.dispatch_workgroups(dimensions.x, dimensions.y, 1);
+}
+
+// Pose the skinned and morphed meshes of every animated model by writing to their range of the
+// vertex buffers.
+pub unsafe extern "C" fn animate_vertices(context: *const rps::CmdCallbackContext) {
+    let context = CmdCallbackContext::<CommandBuffer, UserData, ComputePipeline>::new(context);
+    let pipeline = &context.command_data;
+
+    let encoder = context.command_buffer.encoder.as_mut().unwrap();
+
+    context.user_data.joint_matrices.clear();
+    context.user_data.morph_weights.clear();
+
+    // The first joint and morph weight of each animated model, along with the model.
+    let mut animated_models = Vec::new();
+
+    for model in context.user_data.models.iter().flatten() {
+        if let Some(animation) = &model.animation {
+            let joints = context.user_data.joint_matrices.push(
+                &animation.joint_matrices,
+                &context.user_data.device,
+                &context.user_data.queue,
+                encoder,
+            );
+
+            let morph_weights = context.user_data.morph_weights.push(
+                &animation.morph_weights,
+                &context.user_data.device,
+                &context.user_data.queue,
+                encoder,
+            );
+
+            animated_models.push((joints.start, morph_weights.start, animation));
+        }
+    }
+
+    if animated_models.is_empty() {
+        return;
+    }
+
+    let vertex_buffers = context.user_data.vertex_buffers.buffers.load();
+    let animated_vertex_buffers = context.user_data.animated_vertex_buffers.buffers.load();
+    let morph_target_deltas = context.user_data.morph_target_deltas.buffer.load();
+
+    let bind_group = pipeline.bind_group_layouts.create_bind_group(
+        &context.user_data.device,
+        0,
+        &mut vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: animated_vertex_buffers.position.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: animated_vertex_buffers.normal.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: animated_vertex_buffers.joint_indices.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: animated_vertex_buffers.joint_weights.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: context.user_data.joint_matrices.buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: morph_target_deltas.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: context.user_data.morph_weights.buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: vertex_buffers.position.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: vertex_buffers.normal.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 9,
+                resource: animated_vertex_buffers.tangent.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 10,
+                resource: vertex_buffers.tangent.as_entire_binding(),
+            },
+        ],
+    );
+
+    let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+
+    compute_pass.set_pipeline(&pipeline.pipeline);
+    compute_pass.set_bind_group(0, &bind_group, &[]);
+
+    for (first_joint, first_morph_weight, animation) in animated_models {
+        for primitive in &animation.primitives {
+            let count = primitive.source.end - primitive.source.start;
+
+            let joint_offset = match primitive.skin {
+                Some(skin) => first_joint + animation.skin_offsets[skin],
+                None => u32::max_value(),
+            };
+
+            let (deltas_start, num_targets, morph_weight_offset) = match &primitive.morph_targets {
+                Some(targets) => (
+                    targets.deltas_start,
+                    targets.count,
+                    first_morph_weight + animation.morph_weight_offsets[targets.mesh],
+                ),
+                None => (0, 0, 0),
+            };
+
+            compute_pass.set_push_constants(
+                0,
+                bytemuck::cast_slice(&[
+                    primitive.source.start,
+                    primitive.dest_start,
+                    count,
+                    joint_offset,
+                    deltas_start,
+                    num_targets,
+                    morph_weight_offset,
+                    0,
+                ]),
+            );
+            compute_pass.dispatch_workgroups(dispatch_count(count, 64), 1, 1);
+        }
+    }
+}
+
+// Convolve the skybox with the GGX lobe into the mips of the specular cubemap, with the roughness
+// increasing by mip. This only needs to happen once, so later calls do nothing.
+pub unsafe extern "C" fn prefilter_specular(context: *const rps::CmdCallbackContext) {
+    let context = CmdCallbackContext::<CommandBuffer, UserData, ComputePipeline>::new(context);
+    let pipeline = &context.command_data;
+
+    if context.user_data.ibl.prefiltered_specular {
+        return;
+    }
+
+    let ibl = &context.user_data.ibl;
+    let source = &context.user_data.cubemap.texture;
+    let source_view = context.user_data.cubemap.create_view();
+
+    let encoder = context.command_buffer.encoder.as_mut().unwrap();
+    let mip_count = ibl.specular.mip_level_count();
+
+    for mip in 0..mip_count {
+        let size = ibl.specular.width() >> mip;
+        let output_view = ibl::storage_view(&ibl.specular, mip);
+
+        let bind_group = pipeline.bind_group_layouts.create_bind_group(
+            &context.user_data.device,
+            0,
+            &mut vec![
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&source_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&ibl.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&output_view),
+                },
+            ],
+        );
+
+        let roughness = mip as f32 / (mip_count - 1) as f32;
+
+        let mut compute_pass =
+            encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+
+        compute_pass.set_pipeline(&pipeline.pipeline);
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.set_push_constants(
+            0,
+            bytemuck::cast_slice(&[
+                roughness,
+                source.width() as f32,
+                source.mip_level_count() as f32,
+            ]),
+        );
+        compute_pass.dispatch_workgroups(dispatch_count(size, 8), dispatch_count(size, 8), 6);
+    }
+
+    context.user_data.ibl.prefiltered_specular = true;
+}
+
+// Integrate the skybox over the hemisphere into the diffuse irradiance cubemap, once.
+pub unsafe extern "C" fn compute_irradiance(context: *const rps::CmdCallbackContext) {
+    let context = CmdCallbackContext::<CommandBuffer, UserData, ComputePipeline>::new(context);
+    let pipeline = &context.command_data;
+
+    if context.user_data.ibl.computed_irradiance {
+        return;
+    }
+
+    let ibl = &context.user_data.ibl;
+    let source = &context.user_data.cubemap.texture;
+    let size = ibl.irradiance.width();
+    let output_view = ibl::storage_view(&ibl.irradiance, 0);
+
+    let bind_group = pipeline.bind_group_layouts.create_bind_group(
+        &context.user_data.device,
+        0,
+        &mut vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(
+                    &context.user_data.cubemap.create_view(),
+                ),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&ibl.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&output_view),
+            },
+        ],
+    );
+
+    // Sample from a mip around 64 texels wide, which is dense enough for the irradiance samples
+    // to cover every texel.
+    let source_lod = (source.width() as f32 / 64.0)
+        .log2()
+        .clamp(0.0, (source.mip_level_count() - 1) as f32);
+
+    let mut compute_pass = context
+        .command_buffer
+        .encoder
+        .as_mut()
+        .unwrap()
+        .begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+
+    compute_pass.set_pipeline(&pipeline.pipeline);
+    compute_pass.set_bind_group(0, &bind_group, &[]);
+    compute_pass.set_push_constants(0, bytemuck::bytes_of(&source_lod));
+    compute_pass.dispatch_workgroups(dispatch_count(size, 8), dispatch_count(size, 8), 6);
+
+    context.user_data.ibl.computed_irradiance = true;
+}
+
+// Fill in the split-sum BRDF lookup table, once.
+pub unsafe extern "C" fn integrate_brdf(context: *const rps::CmdCallbackContext) {
+    let context = CmdCallbackContext::<CommandBuffer, UserData, ComputePipeline>::new(context);
+    let pipeline = &context.command_data;
+
+    if context.user_data.ibl.integrated_brdf {
+        return;
+    }
+
+    let brdf_lut = &context.user_data.ibl.brdf_lut;
+
+    let bind_group = pipeline.bind_group_layouts.create_bind_group(
+        &context.user_data.device,
+        0,
+        &mut vec![wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(&context.user_data.ibl.brdf_lut_view()),
+        }],
+    );
+
+    let mut compute_pass = context
+        .command_buffer
+        .encoder
+        .as_mut()
+        .unwrap()
+        .begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+
+    compute_pass.set_pipeline(&pipeline.pipeline);
+    compute_pass.set_bind_group(0, &bind_group, &[]);
+    compute_pass.dispatch_workgroups(
+        dispatch_count(brdf_lut.width(), 8),
+        dispatch_count(brdf_lut.height(), 8),
+        1,
+    );
+
+    context.user_data.ibl.integrated_brdf = true;
+}
+
 pub unsafe extern "C" fn downsample_initial(context: *const rps::CmdCallbackContext) {
     let context = CmdCallbackContext::<CommandBuffer, UserData, ComputePipeline>::new(context);
     let pipeline = &context.command_data;
@@ -360,10 +967,7 @@ pub unsafe extern "C" fn tonemap(context: *const rps::CmdCallbackContext) {
             wgpu::BindGroupEntry {
                 binding: 1,
                 resource: wgpu::BindingResource::TextureView(
-                    &context
-                        .user_data
-                        .tonemap_tex
-                        .create_view(&Default::default()),
+                    &context.user_data.tonemap_tex.create_view(),
                 ),
             },
             wgpu::BindGroupEntry {
@@ -482,13 +1086,7 @@ pub unsafe extern "C" fn render_skybox(context: *const rps::CmdCallbackContext)
             wgpu::BindGroupEntry {
                 binding: 0,
                 resource: wgpu::BindingResource::TextureView(
-                    &context
-                        .user_data
-                        .cubemap
-                        .create_view(&wgpu::TextureViewDescriptor {
-                            dimension: Some(wgpu::TextureViewDimension::Cube),
-                            ..Default::default()
-                        }),
+                    &context.user_data.cubemap.create_view(),
                 ),
             },
             wgpu::BindGroupEntry {
@@ -543,21 +1141,119 @@ pub unsafe extern "C" fn render_ui(context: *const rps::CmdCallbackContext) {
     let (view, img_desc) = load_texture_view(&context, image_view);
 
     let user_data = &mut context.user_data;
+
+    // There's no UI to draw when rendering headlessly.
+    let window = match user_data.window.as_ref() {
+        Some(window) => window,
+        None => return,
+    };
+
     user_data.platform.begin_frame();
     let ctx = user_data.platform.context();
+    let mut model_to_unload = None;
+    let mut compact_buffers = false;
     egui::Window::new("Controls").show(&ctx, |ui| {
         ui.add(egui::widgets::DragValue::new(&mut user_data.skybox_boost));
         ui.add(egui::widgets::DragValue::new(&mut user_data.filter_constants[0]).speed(0.05));
         ui.add(egui::widgets::DragValue::new(&mut user_data.filter_constants[1]).speed(0.05));
+
+        for (index, model) in user_data.models.iter().enumerate() {
+            if model.is_some() && ui.button(format!("Unload model {}", index)).clicked() {
+                model_to_unload = Some(crate::instances::ModelHandle(index));
+            }
+        }
+
+        ui.separator();
+
+        let mut light_to_remove = None;
+
+        for (index, light) in user_data.lights.lights.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(format!("Light {}", index));
+                ui.color_edit_button_rgb(light.color.as_mut());
+                ui.add(
+                    egui::widgets::DragValue::new(&mut light.intensity)
+                        .speed(0.1)
+                        .clamp_range(0.0..=f32::MAX),
+                );
+
+                if light.ty != crate::lights::Light::DIRECTIONAL {
+                    ui.add(egui::widgets::DragValue::new(&mut light.position.x).speed(0.1));
+                    ui.add(egui::widgets::DragValue::new(&mut light.position.y).speed(0.1));
+                    ui.add(egui::widgets::DragValue::new(&mut light.position.z).speed(0.1));
+                }
+
+                if ui.button("Remove").clicked() {
+                    light_to_remove = Some(index);
+                }
+            });
+        }
+
+        if let Some(index) = light_to_remove {
+            user_data.lights.lights.remove(index);
+        }
+
+        let camera = &user_data.camera_rig.final_transform;
+
+        ui.horizontal(|ui| {
+            if ui.button("Add point light").clicked() {
+                user_data.lights.lights.push(crate::lights::Light::point(
+                    camera.position,
+                    glam::Vec3::ONE,
+                    10.0,
+                    None,
+                ));
+            }
+
+            if ui.button("Add directional light").clicked() {
+                user_data
+                    .lights
+                    .lights
+                    .push(crate::lights::Light::directional(
+                        camera.forward(),
+                        glam::Vec3::ONE,
+                        1.0,
+                    ));
+            }
+        });
+
+        ui.separator();
+
+        let shadow_settings = &mut user_data.shadows.settings;
+        ui.label("Shadows");
+        ui.add(
+            egui::widgets::Slider::new(&mut shadow_settings.split_lambda, 0.0..=1.0)
+                .text("Cascade split lambda"),
+        );
+        ui.add(
+            egui::widgets::Slider::new(&mut shadow_settings.max_distance, 1.0..=500.0)
+                .logarithmic(true)
+                .text("Shadow distance"),
+        );
+        ui.add(
+            egui::widgets::Slider::new(&mut shadow_settings.depth_bias, 0.0..=0.01)
+                .logarithmic(true)
+                .text("Depth bias"),
+        );
+        ui.add(
+            egui::widgets::Slider::new(&mut shadow_settings.normal_bias, 0.0..=5.0)
+                .text("Normal bias"),
+        );
+
+        ui.separator();
+
+        ui.label(format!("Vertices: {}", user_data.vertex_buffers.stats()));
+        ui.label(format!("Indices: {}", user_data.index_buffer.stats()));
+        compact_buffers = ui.button("Compact buffers").clicked();
     });
 
-    let full_output = user_data.platform.end_frame(Some(&user_data.window));
+    let full_output = user_data.platform.end_frame(Some(window));
     let paint_jobs = user_data.platform.context().tessellate(full_output.shapes);
 
     let screen_descriptor = ScreenDescriptor {
         physical_width: img_desc.width,
         physical_height: img_desc.height,
-        scale_factor: user_data.window.scale_factor() as f32,
+        scale_factor: window.scale_factor() as f32,
     };
 
     let tdelta: egui::TexturesDelta = full_output.textures_delta;
@@ -583,6 +1279,15 @@ pub unsafe extern "C" fn render_ui(context: *const rps::CmdCallbackContext) {
             None,
         )
         .unwrap();
+
+    if let Some(handle) = model_to_unload {
+        user_data.unload_model(handle);
+    }
+
+    // Recorded after this frame's draws, which still use the old buffers.
+    if compact_buffers {
+        user_data.compact_buffers(context.command_buffer.encoder.as_mut().unwrap());
+    }
 }
 
 pub unsafe extern "C" fn dof_downsample_with_coc(context: *const rps::CmdCallbackContext) {
@@ -964,6 +1669,26 @@ pub unsafe extern "C" fn fft_kernel_transform(context: *const rps::CmdCallbackCo
     );
 }
 
+// The view and projection matrices of the camera, for a target of the given size.
+fn camera_matrices(camera_rig: &dolly::rig::CameraRig, width: u32, height: u32) -> (Mat4, Mat4) {
+    let view_matrix = Mat4::look_at_rh(
+        camera_rig.final_transform.position,
+        camera_rig.final_transform.position + camera_rig.final_transform.forward(),
+        camera_rig.final_transform.up(),
+    );
+
+    let perspective_matrix = Mat4::perspective_infinite_reverse_rh(
+        CAMERA_FOV_Y,
+        width as f32 / height as f32,
+        CAMERA_NEAR,
+    );
+
+    (view_matrix, perspective_matrix)
+}
+
+const CAMERA_NEAR: f32 = 0.001;
+const CAMERA_FOV_Y: f32 = 59.0 * std::f32::consts::PI / 180.0;
+
 const fn dispatch_count(num: u32, group_size: u32) -> u32 {
     ((num - 1) / group_size) + 1
 }
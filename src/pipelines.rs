@@ -1,3 +1,4 @@
+use crate::bindless_textures;
 use crate::reflection;
 use spirq::ty::{ScalarType, Type, VectorType};
 use std::collections::{BTreeMap, HashSet};
@@ -103,6 +104,32 @@ impl BindGroupLayouts {
     }
 }
 
+// A bind group that is kept between frames and only recreated when its key changes, e.g. when
+// one of the resources in it has been reallocated.
+pub struct CachedBindGroup<K> {
+    inner: Option<(K, wgpu::BindGroup)>,
+}
+
+impl<K> Default for CachedBindGroup<K> {
+    fn default() -> Self {
+        Self { inner: None }
+    }
+}
+
+impl<K: PartialEq> CachedBindGroup<K> {
+    pub fn get_or_create(
+        &mut self,
+        key: K,
+        create: impl FnOnce() -> wgpu::BindGroup,
+    ) -> &wgpu::BindGroup {
+        if !matches!(&self.inner, Some((cached_key, _)) if *cached_key == key) {
+            self.inner = Some((key, create()));
+        }
+
+        &self.inner.as_ref().unwrap().1
+    }
+}
+
 pub struct ComputePipeline {
     pub bind_group_layouts: BindGroupLayouts,
     pub pipeline: wgpu::ComputePipeline,
@@ -118,7 +145,11 @@ impl ComputePipeline {
     ) -> Self {
         let shader_bytes = shader.load(entry_point, "cs_6_0");
 
-        let reflection = reflection::reflect(&shader_bytes, reflection_settings);
+        let reflection = reflection::reflect(
+            &shader_bytes,
+            reflection_settings,
+            bindless_textures::array_length(&device.limits()),
+        );
 
         assert_eq!(reflection.entry_points.len(), 1);
 
@@ -157,6 +188,7 @@ pub struct RenderPipeline {
 }
 
 impl RenderPipeline {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &wgpu::Device,
         shader: &ShaderSource,
@@ -165,14 +197,25 @@ impl RenderPipeline {
         targets: &[Option<wgpu::ColorTargetState>],
         depth_stencil: Option<wgpu::DepthStencilState>,
         vertex_buffer_layouts: &[wgpu::VertexBufferLayout],
+        reflection_settings: &reflection::ReflectionSettings,
         raw_spirv: bool,
     ) -> Self {
         let vertex_shader_bytes = shader.load(vertex_entry_point, "vs_6_0");
         let fragment_shader_bytes = shader.load(fragment_entry_point, "ps_6_0");
 
-        let vertex_reflection = reflection::reflect(&vertex_shader_bytes, &Default::default());
+        let runtime_array_length = bindless_textures::array_length(&device.limits());
 
-        let fragment_reflection = reflection::reflect(&fragment_shader_bytes, &Default::default());
+        let vertex_reflection = reflection::reflect(
+            &vertex_shader_bytes,
+            reflection_settings,
+            runtime_array_length,
+        );
+
+        let fragment_reflection = reflection::reflect(
+            &fragment_shader_bytes,
+            reflection_settings,
+            runtime_array_length,
+        );
 
         let bindings = reflection::merge_bind_group_layout_entries(
             &vertex_reflection.bindings,
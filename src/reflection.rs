@@ -14,12 +14,15 @@ fn map_dim(dim: spirv::Dim, is_array: bool) -> wgpu::TextureViewDimension {
 #[derive(Clone)]
 pub struct ReflectionSettings {
     pub override_sampled_texture_ty: Option<(u32, wgpu::TextureSampleType)>,
+    // The binding of a sampler to use for depth comparisons instead of filtering.
+    pub comparison_sampler: Option<u32>,
 }
 
 impl Default for ReflectionSettings {
     fn default() -> Self {
         Self {
             override_sampled_texture_ty: None,
+            comparison_sampler: None,
         }
     }
 }
@@ -32,7 +35,12 @@ pub struct Reflection {
 
 impl Reflection {}
 
-pub fn reflect(bytes: &[u8], settings: &ReflectionSettings) -> Reflection {
+// Runtime-sized arrays such as the bindless textures are given `runtime_array_length` bindings.
+pub fn reflect(
+    bytes: &[u8],
+    settings: &ReflectionSettings,
+    runtime_array_length: u32,
+) -> Reflection {
     let entry_points = spirq::ReflectConfig::new()
         .ref_all_rscs(true)
         .spv(bytes)
@@ -107,6 +115,12 @@ pub fn reflect(bytes: &[u8], settings: &ReflectionSettings) -> Reflection {
                                         other => panic!("{:?}", other),
                                     },
                                 },
+                                (
+                                    spirq::ty::Type::Sampler(),
+                                    spirq::reflect::DescriptorType::Sampler(),
+                                ) if settings.comparison_sampler == Some(desc_bind.bind()) => {
+                                    wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison)
+                                }
                                 (
                                     spirq::ty::Type::Sampler(),
                                     spirq::reflect::DescriptorType::Sampler(),
@@ -136,7 +150,7 @@ pub fn reflect(bytes: &[u8], settings: &ReflectionSettings) -> Reflection {
                                 other => panic!("{:?}", other),
                             },
                             count: match *nbind {
-                                0 => Some(std::num::NonZeroU32::new(4096).unwrap()),
+                                0 => std::num::NonZeroU32::new(runtime_array_length),
                                 1 => None,
                                 other => Some(std::num::NonZeroU32::new(other).unwrap()),
                             },
@@ -0,0 +1,144 @@
+use crate::lights::Light;
+use glam::{Quat, Vec3};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+// A RON description of everything that gets loaded at startup. Paths are relative to the
+// working directory.
+#[derive(Deserialize)]
+pub struct Scene {
+    pub camera: Camera,
+    pub skybox: PathBuf,
+    pub tonemap_lut: PathBuf,
+    // Textures passed to the render graph entry point after the back buffer, in order.
+    #[serde(default)]
+    pub graph_inputs: Vec<PathBuf>,
+    #[serde(default)]
+    pub models: Vec<SceneModel>,
+    #[serde(default)]
+    pub lights: Vec<SceneLight>,
+}
+
+impl Scene {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| anyhow::anyhow!("Failed to read {}: {}", path.display(), error))?;
+
+        ron::from_str(&text)
+            .map_err(|error| anyhow::anyhow!("Failed to parse {}: {}", path.display(), error))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Camera {
+    pub position: Vec3,
+    #[serde(default)]
+    pub yaw: f32,
+    #[serde(default)]
+    pub pitch: f32,
+}
+
+impl Camera {
+    pub fn create_rig(&self) -> dolly::rig::CameraRig {
+        dolly::rig::CameraRig::builder()
+            .with(dolly::drivers::Position::new(self.position))
+            .with(
+                dolly::drivers::YawPitch::new()
+                    .yaw_degrees(self.yaw)
+                    .pitch_degrees(self.pitch),
+            )
+            .with(dolly::drivers::Smooth::new_position_rotation(0.5, 0.5))
+            .build()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SceneModel {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub transform: Transform,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct Transform {
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub scale: f32,
+    // Radians per second around the Y axis.
+    pub spin: f32,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            position: Vec3::ZERO,
+            rotation: Quat::IDENTITY,
+            scale: 1.0,
+            spin: 0.0,
+        }
+    }
+}
+
+// A light in world space. Directions are the way the light points, and cone angles are in
+// radians.
+#[derive(Deserialize)]
+pub enum SceneLight {
+    Directional {
+        direction: Vec3,
+        color: Vec3,
+        intensity: f32,
+    },
+    Point {
+        position: Vec3,
+        color: Vec3,
+        intensity: f32,
+        #[serde(default)]
+        range: Option<f32>,
+    },
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        color: Vec3,
+        intensity: f32,
+        #[serde(default)]
+        range: Option<f32>,
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    },
+}
+
+impl SceneLight {
+    pub fn to_light(&self) -> Light {
+        match *self {
+            Self::Directional {
+                direction,
+                color,
+                intensity,
+            } => Light::directional(direction, color, intensity),
+            Self::Point {
+                position,
+                color,
+                intensity,
+                range,
+            } => Light::point(position, color, intensity, range),
+            Self::Spot {
+                position,
+                direction,
+                color,
+                intensity,
+                range,
+                inner_cone_angle,
+                outer_cone_angle,
+            } => Light::spot(
+                position,
+                direction,
+                color,
+                intensity,
+                range,
+                inner_cone_angle,
+                outer_cone_angle,
+            ),
+        }
+    }
+}
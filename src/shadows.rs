@@ -0,0 +1,222 @@
+use glam::{Mat4, Vec3, Vec4};
+
+// Matches `CASCADE_COUNT` in `shadows.hlsl`.
+pub const CASCADE_COUNT: usize = 4;
+const SHADOW_MAP_SIZE: u32 = 2048;
+// Where the first cascade starts.
+const CASCADE_NEAR: f32 = 0.1;
+// How far the shadow maps extend towards the light beyond the camera frustum, so that objects
+// outside of the frustum can still cast shadows into it.
+const CASTER_MARGIN: f32 = 50.0;
+
+// Laid out to match `ShadowUniforms` in `shadows.hlsl`.
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+pub struct ShadowUniforms {
+    pub cascade_matrices: [Mat4; CASCADE_COUNT],
+    // The view space distance at which each cascade ends.
+    pub cascade_splits: Vec4,
+    // The world space size of a shadow map texel in each cascade.
+    pub cascade_texel_sizes: Vec4,
+    // The index of the shadow casting light in the light buffer, or `u32::MAX` if there isn't
+    // one.
+    pub light_index: u32,
+    pub depth_bias: f32,
+    pub normal_bias: f32,
+    pub _padding: u32,
+}
+
+impl ShadowUniforms {
+    // Used when there's no directional light, so that nothing is shadowed.
+    pub fn disabled() -> Self {
+        Self {
+            cascade_matrices: [Mat4::IDENTITY; CASCADE_COUNT],
+            cascade_splits: Vec4::ZERO,
+            cascade_texel_sizes: Vec4::ZERO,
+            light_index: u32::MAX,
+            depth_bias: 0.0,
+            normal_bias: 0.0,
+            _padding: 0,
+        }
+    }
+}
+
+pub struct ShadowSettings {
+    // Blends the cascade splits between an even spacing (0) and a logarithmic one (1).
+    pub split_lambda: f32,
+    // The distance from the camera at which the last cascade ends.
+    pub max_distance: f32,
+    // Subtracted from the depth of each fragment when comparing it to the shadow map.
+    pub depth_bias: f32,
+    // How far fragments are pushed along their normal before being looked up, in texels.
+    pub normal_bias: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            split_lambda: 0.75,
+            max_distance: 50.0,
+            depth_bias: 0.0005,
+            normal_bias: 1.5,
+        }
+    }
+}
+
+// Cascaded shadow maps for the first directional light, rendered by `render_shadows` and sampled
+// by `draw`.
+pub struct ShadowMaps {
+    // A layer for each cascade.
+    pub texture: wgpu::Texture,
+    pub uniforms: wgpu::Buffer,
+    pub sampler: wgpu::Sampler,
+    pub settings: ShadowSettings,
+}
+
+impl ShadowMaps {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            texture: device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("shadow maps"),
+                size: wgpu::Extent3d {
+                    width: SHADOW_MAP_SIZE,
+                    height: SHADOW_MAP_SIZE,
+                    depth_or_array_layers: CASCADE_COUNT as u32,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Depth32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            }),
+            uniforms: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("shadow uniforms"),
+                size: std::mem::size_of::<ShadowUniforms>() as u64,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            sampler: device.create_sampler(&wgpu::SamplerDescriptor {
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                compare: Some(wgpu::CompareFunction::LessEqual),
+                ..Default::default()
+            }),
+            settings: Default::default(),
+        }
+    }
+
+    pub fn array_view(&self) -> wgpu::TextureView {
+        self.texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        })
+    }
+
+    pub fn cascade_view(&self, cascade: u32) -> wgpu::TextureView {
+        self.texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_array_layer: cascade,
+            array_layer_count: Some(1),
+            ..Default::default()
+        })
+    }
+
+    // Split the view frustum into cascades using the practical split scheme, which blends
+    // between even and logarithmic spacing.
+    fn cascade_splits(&self) -> [f32; CASCADE_COUNT] {
+        let near = CASCADE_NEAR;
+        let far = self.settings.max_distance.max(near * 2.0);
+        let lambda = self.settings.split_lambda.clamp(0.0, 1.0);
+
+        let mut splits = [0.0; CASCADE_COUNT];
+
+        for (i, split) in splits.iter_mut().enumerate() {
+            let fraction = (i + 1) as f32 / CASCADE_COUNT as f32;
+            let logarithmic = near * (far / near).powf(fraction);
+            let uniform = near + (far - near) * fraction;
+            *split = lambda * logarithmic + (1.0 - lambda) * uniform;
+        }
+
+        splits
+    }
+
+    // Fit a light space projection around each cascade's slice of the camera frustum. The slices
+    // are bounded by spheres and snapped to shadow map texels, so that the shadows don't shimmer
+    // as the camera moves and turns.
+    pub fn uniforms(
+        &self,
+        camera_view: Mat4,
+        fov_y: f32,
+        aspect_ratio: f32,
+        light_direction: Vec3,
+        light_index: u32,
+    ) -> ShadowUniforms {
+        let splits = self.cascade_splits();
+        let camera_to_world = camera_view.inverse();
+        let tan_half_fov = (fov_y * 0.5).tan();
+
+        let light_direction = light_direction.normalize();
+        let up = if light_direction.y.abs() > 0.99 {
+            Vec3::Z
+        } else {
+            Vec3::Y
+        };
+        let light_rotation = Mat4::look_at_rh(Vec3::ZERO, light_direction, up);
+
+        let mut cascade_matrices = [Mat4::IDENTITY; CASCADE_COUNT];
+        let mut cascade_texel_sizes = [0.0; CASCADE_COUNT];
+        let mut near = CASCADE_NEAR;
+
+        for (i, &far) in splits.iter().enumerate() {
+            let corners = [near, far].into_iter().flat_map(|distance| {
+                let y = distance * tan_half_fov;
+                let x = y * aspect_ratio;
+
+                [(-x, -y), (x, -y), (-x, y), (x, y)]
+                    .map(|(x, y)| camera_to_world.transform_point3(Vec3::new(x, y, -distance)))
+            });
+
+            let corners: Vec<Vec3> = corners.collect();
+            let center = corners.iter().sum::<Vec3>() / corners.len() as f32;
+            let radius = corners
+                .iter()
+                .map(|corner| corner.distance(center))
+                .fold(0.0_f32, f32::max);
+
+            let texel_size = radius * 2.0 / SHADOW_MAP_SIZE as f32;
+
+            let mut light_space_center = light_rotation.transform_point3(center);
+            light_space_center.x = (light_space_center.x / texel_size).floor() * texel_size;
+            light_space_center.y = (light_space_center.y / texel_size).floor() * texel_size;
+            let center = light_rotation
+                .inverse()
+                .transform_point3(light_space_center);
+
+            let light_view = Mat4::look_at_rh(center, center + light_direction, up);
+            let projection = Mat4::orthographic_rh(
+                -radius,
+                radius,
+                -radius,
+                radius,
+                -radius - CASTER_MARGIN,
+                radius,
+            );
+
+            cascade_matrices[i] = projection * light_view;
+            cascade_texel_sizes[i] = texel_size;
+            near = far;
+        }
+
+        ShadowUniforms {
+            cascade_matrices,
+            cascade_splits: Vec4::from(splits),
+            cascade_texel_sizes: Vec4::from(cascade_texel_sizes),
+            light_index,
+            depth_bias: self.settings.depth_bias,
+            normal_bias: self.settings.normal_bias,
+            _padding: 0,
+        }
+    }
+}
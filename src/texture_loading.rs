@@ -1,77 +1,1231 @@
-use std::borrow::Cow;
+use crate::mip_generation::MipGenerator;
+use std::path::Path;
 use wgpu::util::DeviceExt;
 
-pub fn load_ktx2(bytes: &[u8], device: &wgpu::Device, queue: &wgpu::Queue) -> wgpu::Texture {
-    let reader = ktx2::Reader::new(bytes).unwrap();
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
 
-    let header = reader.header();
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    Ktx2,
+    Png,
+    Jpeg,
+}
 
-    let mut bytes = vec![
-        0;
-        reader
-            .levels()
-            .map(|level| level.uncompressed_byte_length as usize)
-            .sum()
-    ];
-    let mut offset = 0;
-
-    for level in reader.levels() {
-        match header.supercompression_scheme {
-            Some(ktx2::SupercompressionScheme::Zstandard) => {
-                zstd::bulk::decompress_to_buffer(level.data, &mut bytes[offset..]).unwrap();
-            }
-            None => bytes[offset..level.data.len()].copy_from_slice(&level.data),
-            other => panic!("{:?}", other),
+impl ImageFormat {
+    pub fn from_mime_type(mime_type: &str) -> Option<Self> {
+        match mime_type {
+            "image/ktx2" => Some(Self::Ktx2),
+            "image/png" => Some(Self::Png),
+            "image/jpeg" => Some(Self::Jpeg),
+            _ => None,
         }
-        offset += level.uncompressed_byte_length as usize;
     }
 
-    // Swizzle bytes from being like (F = face, M = mip) F0M0 F1M0.. to being F0M0 F0M1..
-    // todo: could do this inline possibly?
-    if header.face_count == 6 {
-        let mut swizzled_bytes = Vec::with_capacity(bytes.len());
-        for i in 0..6 {
-            let mut offset = 0;
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "ktx2" => Some(Self::Ktx2),
+            "png" => Some(Self::Png),
+            "jpg" | "jpeg" => Some(Self::Jpeg),
+            _ => None,
+        }
+    }
 
-            for level in reader.levels() {
-                let face_length = level.uncompressed_byte_length as usize / 6;
+    // Guess the format from the magic bytes at the start of the file.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&KTX2_IDENTIFIER) {
+            return Some(Self::Ktx2);
+        }
 
-                swizzled_bytes.extend_from_slice(
-                    &bytes[offset + i * face_length..offset + (i + 1) * face_length],
-                );
+        match image::guess_format(bytes).ok()? {
+            image::ImageFormat::Png => Some(Self::Png),
+            image::ImageFormat::Jpeg => Some(Self::Jpeg),
+            _ => None,
+        }
+    }
+}
+
+// Load an image in any of the supported formats. `srgb` is ignored for KTX2 files as they specify
+// their own format. If a mip generator is given, it's used to build the mip chains of images that
+// don't come with one.
+pub fn load_image(
+    bytes: &[u8],
+    format: ImageFormat,
+    srgb: bool,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    mip_generator: Option<&MipGenerator>,
+) -> anyhow::Result<wgpu::Texture> {
+    match format {
+        ImageFormat::Ktx2 => Ok(load_ktx2(
+            bytes,
+            device,
+            queue,
+            wgpu::TextureUsages::empty(),
+            mip_generator,
+        )?
+        .texture),
+        ImageFormat::Png => load_image_with_mips(
+            bytes,
+            image::ImageFormat::Png,
+            srgb,
+            device,
+            queue,
+            mip_generator,
+        ),
+        ImageFormat::Jpeg => load_image_with_mips(
+            bytes,
+            image::ImageFormat::Jpeg,
+            srgb,
+            device,
+            queue,
+            mip_generator,
+        ),
+    }
+}
+
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).leading_zeros()
+}
+
+// Decode an image and generate a full mip chain for it, as PNGs and JPEGs don't carry their own.
+// The chain is built on the CPU unless a mip generator is given.
+fn load_image_with_mips(
+    bytes: &[u8],
+    format: image::ImageFormat,
+    srgb: bool,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    mip_generator: Option<&MipGenerator>,
+) -> anyhow::Result<wgpu::Texture> {
+    let image = image::load_from_memory_with_format(bytes, format)?.to_rgba8();
 
-                offset += level.uncompressed_byte_length as usize;
+    let (width, height) = image.dimensions();
+    let mip_level_count = mip_level_count(width, height);
+
+    let descriptor = wgpu::TextureDescriptor {
+        label: None,
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: if srgb {
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        } else {
+            wgpu::TextureFormat::Rgba8Unorm
+        },
+        usage: wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    };
+
+    if let Some(mip_generator) = mip_generator {
+        return Ok(mip_generator.create_texture_with_mips(
+            device,
+            queue,
+            &descriptor,
+            image.as_raw(),
+        ));
+    }
+
+    let mut data = image.as_raw().clone();
+    let mut level = image;
+
+    for _ in 1..mip_level_count {
+        level = image::imageops::resize(
+            &level,
+            (level.width() / 2).max(1),
+            (level.height() / 2).max(1),
+            image::imageops::FilterType::Triangle,
+        );
+
+        data.extend_from_slice(level.as_raw());
+    }
+
+    Ok(device.create_texture_with_data(queue, &descriptor, &data))
+}
+
+// Map a KTX2 format to the equivalent wgpu format.
+fn wgpu_format(format: ktx2::Format) -> anyhow::Result<wgpu::TextureFormat> {
+    use wgpu::{AstcBlock, AstcChannel, TextureFormat};
+
+    let astc = |block, channel| TextureFormat::Astc { block, channel };
+
+    macro_rules! astc_formats {
+        ($($block:ident => $unorm:ident, $srgb:ident, $sfloat:ident;)*) => {
+            match format {
+                $(
+                    ktx2::Format::$unorm => return Ok(astc(AstcBlock::$block, AstcChannel::Unorm)),
+                    ktx2::Format::$srgb => return Ok(astc(AstcBlock::$block, AstcChannel::UnormSrgb)),
+                    ktx2::Format::$sfloat => return Ok(astc(AstcBlock::$block, AstcChannel::Hdr)),
+                )*
+                _ => {}
             }
+        };
+    }
+
+    astc_formats! {
+        B4x4 => ASTC_4x4_UNORM_BLOCK, ASTC_4x4_SRGB_BLOCK, ASTC_4x4_SFLOAT_BLOCK;
+        B5x4 => ASTC_5x4_UNORM_BLOCK, ASTC_5x4_SRGB_BLOCK, ASTC_5x4_SFLOAT_BLOCK;
+        B5x5 => ASTC_5x5_UNORM_BLOCK, ASTC_5x5_SRGB_BLOCK, ASTC_5x5_SFLOAT_BLOCK;
+        B6x5 => ASTC_6x5_UNORM_BLOCK, ASTC_6x5_SRGB_BLOCK, ASTC_6x5_SFLOAT_BLOCK;
+        B6x6 => ASTC_6x6_UNORM_BLOCK, ASTC_6x6_SRGB_BLOCK, ASTC_6x6_SFLOAT_BLOCK;
+        B8x5 => ASTC_8x5_UNORM_BLOCK, ASTC_8x5_SRGB_BLOCK, ASTC_8x5_SFLOAT_BLOCK;
+        B8x6 => ASTC_8x6_UNORM_BLOCK, ASTC_8x6_SRGB_BLOCK, ASTC_8x6_SFLOAT_BLOCK;
+        B8x8 => ASTC_8x8_UNORM_BLOCK, ASTC_8x8_SRGB_BLOCK, ASTC_8x8_SFLOAT_BLOCK;
+        B10x5 => ASTC_10x5_UNORM_BLOCK, ASTC_10x5_SRGB_BLOCK, ASTC_10x5_SFLOAT_BLOCK;
+        B10x6 => ASTC_10x6_UNORM_BLOCK, ASTC_10x6_SRGB_BLOCK, ASTC_10x6_SFLOAT_BLOCK;
+        B10x8 => ASTC_10x8_UNORM_BLOCK, ASTC_10x8_SRGB_BLOCK, ASTC_10x8_SFLOAT_BLOCK;
+        B10x10 => ASTC_10x10_UNORM_BLOCK, ASTC_10x10_SRGB_BLOCK, ASTC_10x10_SFLOAT_BLOCK;
+        B12x10 => ASTC_12x10_UNORM_BLOCK, ASTC_12x10_SRGB_BLOCK, ASTC_12x10_SFLOAT_BLOCK;
+        B12x12 => ASTC_12x12_UNORM_BLOCK, ASTC_12x12_SRGB_BLOCK, ASTC_12x12_SFLOAT_BLOCK;
+    }
+
+    Ok(match format {
+        ktx2::Format::E5B9G9R9_UFLOAT_PACK32 => TextureFormat::Rgb9e5Ufloat,
+        ktx2::Format::R8G8B8A8_SRGB => TextureFormat::Rgba8UnormSrgb,
+        ktx2::Format::R8G8B8A8_UNORM => TextureFormat::Rgba8Unorm,
+        ktx2::Format::R16G16B16A16_SFLOAT => TextureFormat::Rgba16Float,
+        // wgpu doesn't distinguish between BC1 with and without alpha.
+        ktx2::Format::BC1_RGB_UNORM_BLOCK | ktx2::Format::BC1_RGBA_UNORM_BLOCK => {
+            TextureFormat::Bc1RgbaUnorm
+        }
+        ktx2::Format::BC1_RGB_SRGB_BLOCK | ktx2::Format::BC1_RGBA_SRGB_BLOCK => {
+            TextureFormat::Bc1RgbaUnormSrgb
+        }
+        ktx2::Format::BC2_UNORM_BLOCK => TextureFormat::Bc2RgbaUnorm,
+        ktx2::Format::BC2_SRGB_BLOCK => TextureFormat::Bc2RgbaUnormSrgb,
+        ktx2::Format::BC3_UNORM_BLOCK => TextureFormat::Bc3RgbaUnorm,
+        ktx2::Format::BC3_SRGB_BLOCK => TextureFormat::Bc3RgbaUnormSrgb,
+        ktx2::Format::BC4_UNORM_BLOCK => TextureFormat::Bc4RUnorm,
+        ktx2::Format::BC4_SNORM_BLOCK => TextureFormat::Bc4RSnorm,
+        ktx2::Format::BC5_UNORM_BLOCK => TextureFormat::Bc5RgUnorm,
+        ktx2::Format::BC5_SNORM_BLOCK => TextureFormat::Bc5RgSnorm,
+        ktx2::Format::BC6H_UFLOAT_BLOCK => TextureFormat::Bc6hRgbUfloat,
+        ktx2::Format::BC6H_SFLOAT_BLOCK => TextureFormat::Bc6hRgbFloat,
+        ktx2::Format::BC7_UNORM_BLOCK => TextureFormat::Bc7RgbaUnorm,
+        ktx2::Format::BC7_SRGB_BLOCK => TextureFormat::Bc7RgbaUnormSrgb,
+        other => return Err(anyhow::anyhow!("Unsupported KTX2 format: {:?}", other)),
+    })
+}
+
+// Decompress a block-compressed image into RGBA8 on the CPU, for when the device doesn't support
+// the format. HDR formats get clamped to [0, 1].
+fn decompress_image(
+    data: &[u8],
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+) -> anyhow::Result<Vec<u8>> {
+    use wgpu::TextureFormat;
+
+    let (width, height) = (width as usize, height as usize);
+    let mut pixels = vec![0_u32; width * height];
+
+    let result = match format {
+        TextureFormat::Bc1RgbaUnorm | TextureFormat::Bc1RgbaUnormSrgb => {
+            texture2ddecoder::decode_bc1(data, width, height, &mut pixels)
+        }
+        TextureFormat::Bc3RgbaUnorm | TextureFormat::Bc3RgbaUnormSrgb => {
+            texture2ddecoder::decode_bc3(data, width, height, &mut pixels)
+        }
+        TextureFormat::Bc4RUnorm => texture2ddecoder::decode_bc4(data, width, height, &mut pixels),
+        TextureFormat::Bc5RgUnorm => texture2ddecoder::decode_bc5(data, width, height, &mut pixels),
+        TextureFormat::Bc6hRgbUfloat => {
+            texture2ddecoder::decode_bc6(data, width, height, &mut pixels, false)
+        }
+        TextureFormat::Bc6hRgbFloat => {
+            texture2ddecoder::decode_bc6(data, width, height, &mut pixels, true)
+        }
+        TextureFormat::Bc7RgbaUnorm | TextureFormat::Bc7RgbaUnormSrgb => {
+            texture2ddecoder::decode_bc7(data, width, height, &mut pixels)
+        }
+        TextureFormat::Astc {
+            channel: wgpu::AstcChannel::Unorm | wgpu::AstcChannel::UnormSrgb,
+            ..
+        } => {
+            let (block_width, block_height) = format.block_dimensions();
+
+            texture2ddecoder::decode_astc(
+                data,
+                width,
+                height,
+                block_width as usize,
+                block_height as usize,
+                &mut pixels,
+            )
         }
+        other => {
+            return Err(anyhow::anyhow!(
+                "Decompressing {:?} textures on the CPU is not supported",
+                other
+            ))
+        }
+    };
+
+    result.map_err(|error| anyhow::anyhow!("Failed to decompress {:?}: {}", format, error))?;
+
+    // The decoder packs pixels as BGRA.
+    Ok(pixels
+        .iter()
+        .flat_map(|pixel| {
+            let [b, g, r, a] = pixel.to_le_bytes();
+            [r, g, b, a]
+        })
+        .collect())
+}
 
-        bytes = swizzled_bytes;
+static BASIS_TRANSCODER_INIT: std::sync::Once = std::sync::Once::new();
+
+// The formats that Basis Universal textures get transcoded to.
+struct BasisTarget {
+    block_format: basis_universal::TranscoderBlockFormat,
+    texture_format: basis_universal::TranscoderTextureFormat,
+    format: wgpu::TextureFormat,
+}
+
+// Pick the best format that the device supports for Basis Universal textures to be transcoded to.
+fn basis_target(features: wgpu::Features, srgb: bool) -> BasisTarget {
+    let target = if features.contains(wgpu::Features::TEXTURE_COMPRESSION_BC) {
+        BasisTarget {
+            block_format: basis_universal::TranscoderBlockFormat::BC7,
+            texture_format: basis_universal::TranscoderTextureFormat::BC7_RGBA,
+            format: wgpu::TextureFormat::Bc7RgbaUnorm,
+        }
+    } else if features.contains(wgpu::Features::TEXTURE_COMPRESSION_ASTC) {
+        BasisTarget {
+            block_format: basis_universal::TranscoderBlockFormat::ASTC_4x4,
+            texture_format: basis_universal::TranscoderTextureFormat::ASTC_4x4_RGBA,
+            format: wgpu::TextureFormat::Astc {
+                block: wgpu::AstcBlock::B4x4,
+                channel: wgpu::AstcChannel::Unorm,
+            },
+        }
+    } else {
+        BasisTarget {
+            block_format: basis_universal::TranscoderBlockFormat::RGBA32,
+            texture_format: basis_universal::TranscoderTextureFormat::RGBA32,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+        }
+    };
+
+    BasisTarget {
+        format: if srgb {
+            target.format.add_srgb_suffix()
+        } else {
+            target.format
+        },
+        ..target
     }
+}
+
+fn transcode_uastc_image(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    block_format: basis_universal::TranscoderBlockFormat,
+) -> anyhow::Result<Vec<u8>> {
+    BASIS_TRANSCODER_INIT.call_once(basis_universal::transcoder_init);
 
-    device.create_texture_with_data(
-        &queue,
-        &wgpu::TextureDescriptor {
-            label: None,
-            size: wgpu::Extent3d {
-                width: header.pixel_width,
-                height: header.pixel_height,
-                depth_or_array_layers: header.pixel_depth.max(1).max(header.face_count),
+    basis_universal::LowLevelUastcTranscoder::new()
+        .transcode_slice(
+            data,
+            basis_universal::SliceParametersUastc {
+                num_blocks_x: (width + 3) / 4,
+                num_blocks_y: (height + 3) / 4,
+                has_alpha: true,
+                original_width: width,
+                original_height: height,
             },
-            mip_level_count: header.level_count,
-            sample_count: 1,
-            dimension: if header.pixel_depth > 1 {
-                wgpu::TextureDimension::D3
+            basis_universal::DecodeFlags::HIGH_QUALITY,
+            block_format,
+        )
+        .map_err(|error| anyhow::anyhow!("Failed to transcode UASTC image: {:?}", error))
+}
+
+// The CRC used to validate `.basis` files.
+fn basis_crc16(bytes: &[u8]) -> u16 {
+    let mut crc = !0_u16;
+
+    for &byte in bytes {
+        let mut x = (crc >> 8) as u8 ^ byte;
+        x ^= x >> 4;
+        crc = (crc << 8) ^ ((x as u16) << 12) ^ ((x as u16) << 5) ^ (x as u16);
+    }
+
+    !crc
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> anyhow::Result<u32> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| anyhow::anyhow!("Unexpected end of data at {}", offset))
+}
+
+// Transcode BasisLZ-supercompressed ETC1S levels. The Basis Universal transcoder only reads ETC1S
+// data from `.basis` files, so the levels and the codebooks in the supercompression global data
+// get repackaged as one first.
+fn transcode_etc1s(
+    levels: &[Vec<u8>],
+    global_data: &[u8],
+    header: &ktx2::Header,
+    target: &BasisTarget,
+    srgb: bool,
+) -> anyhow::Result<Vec<Vec<u8>>> {
+    const HEADER_SIZE: usize = 77;
+    const SLICE_DESC_SIZE: usize = 23;
+
+    let images = array_layer_count(header) as usize;
+
+    let endpoint_count = read_u32(global_data, 0)? & 0xffff;
+    let selector_count = read_u32(global_data, 0)? >> 16;
+    let endpoints_length = read_u32(global_data, 4)? as usize;
+    let selectors_length = read_u32(global_data, 8)? as usize;
+    let tables_length = read_u32(global_data, 12)? as usize;
+
+    // Image descriptions are ordered by level and then layer, with each one containing the flags,
+    // offset and length of the RGB slice and then the offset and length of the alpha slice.
+    let image_descs_start = 20;
+    let image_desc = |level: usize, image: usize| -> anyhow::Result<[u32; 5]> {
+        let offset = image_descs_start + (level * images + image) * 20;
+
+        Ok([
+            read_u32(global_data, offset)?,
+            read_u32(global_data, offset + 4)?,
+            read_u32(global_data, offset + 8)?,
+            read_u32(global_data, offset + 12)?,
+            read_u32(global_data, offset + 16)?,
+        ])
+    };
+
+    let codebooks_start = image_descs_start + levels.len() * images * 20;
+    let codebooks = global_data
+        .get(codebooks_start..codebooks_start + endpoints_length + selectors_length + tables_length)
+        .ok_or_else(|| anyhow::anyhow!("BasisLZ global data is too short"))?;
+
+    let has_alpha = (0..levels.len())
+        .flat_map(|level| (0..images).map(move |image| (level, image)))
+        .map(|(level, image)| image_desc(level, image).map(|desc| desc[4] > 0))
+        .collect::<anyhow::Result<Vec<_>>>()?
+        .into_iter()
+        .any(|has_alpha| has_alpha);
+
+    let slices_per_image = if has_alpha { 2 } else { 1 };
+    let slice_count = images * levels.len() * slices_per_image;
+
+    let slice_descs_start = HEADER_SIZE;
+    let codebooks_offset = slice_descs_start + slice_count * SLICE_DESC_SIZE;
+    let mut slice_data_offset = codebooks_offset + codebooks.len();
+
+    let mut slice_descs = Vec::with_capacity(slice_count * SLICE_DESC_SIZE);
+    let mut slice_data = Vec::new();
+
+    // `.basis` slices are ordered by image and then level, with alpha slices directly following
+    // their RGB slices.
+    for image in 0..images {
+        for (level, level_bytes) in levels.iter().enumerate() {
+            let [_flags, rgb_offset, rgb_length, alpha_offset, alpha_length] =
+                image_desc(level, image)?;
+
+            let width = (header.pixel_width >> level).max(1);
+            let height = (header.pixel_height >> level).max(1);
+
+            let mut slices = vec![(rgb_offset, rgb_length, 0_u8)];
+
+            if has_alpha {
+                slices.push((alpha_offset, alpha_length, 1));
+            }
+
+            for (offset, length, flags) in slices {
+                let data = level_bytes
+                    .get(offset as usize..(offset + length) as usize)
+                    .ok_or_else(|| anyhow::anyhow!("ETC1S slice is out of range"))?;
+
+                slice_descs.extend_from_slice(&(image as u32).to_le_bytes()[..3]);
+                slice_descs.push(level as u8);
+                slice_descs.push(flags);
+                slice_descs.extend_from_slice(&(width as u16).to_le_bytes());
+                slice_descs.extend_from_slice(&(height as u16).to_le_bytes());
+                slice_descs.extend_from_slice(&(((width + 3) / 4) as u16).to_le_bytes());
+                slice_descs.extend_from_slice(&(((height + 3) / 4) as u16).to_le_bytes());
+                slice_descs.extend_from_slice(&(slice_data_offset as u32).to_le_bytes());
+                slice_descs.extend_from_slice(&length.to_le_bytes());
+                slice_descs.extend_from_slice(&basis_crc16(data).to_le_bytes());
+
+                slice_data.extend_from_slice(data);
+                slice_data_offset += data.len();
+            }
+        }
+    }
+
+    let mut data = slice_descs;
+    data.extend_from_slice(codebooks);
+    data.extend_from_slice(&slice_data);
+
+    // ETC1S, with alpha slices and sRGB as needed.
+    let flags = 1 | if has_alpha { 4 } else { 0 } | if srgb { 16 } else { 0 };
+
+    let mut basis_header = Vec::with_capacity(HEADER_SIZE);
+    basis_header.extend_from_slice(&u16::from_le_bytes(*b"sB").to_le_bytes());
+    basis_header.extend_from_slice(&0x13_u16.to_le_bytes());
+    basis_header.extend_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
+    // Header CRC, filled in below.
+    basis_header.extend_from_slice(&0_u16.to_le_bytes());
+    basis_header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    basis_header.extend_from_slice(&basis_crc16(&data).to_le_bytes());
+    basis_header.extend_from_slice(&(slice_count as u32).to_le_bytes()[..3]);
+    basis_header.extend_from_slice(&(images as u32).to_le_bytes()[..3]);
+    // Texture format (ETC1S).
+    basis_header.push(0);
+    basis_header.extend_from_slice(&(flags as u16).to_le_bytes());
+    // Texture type (2D).
+    basis_header.push(0);
+    // Microseconds per frame, reserved and two user data fields.
+    basis_header.extend_from_slice(&[0; 3 + 4 + 4 + 4]);
+    basis_header.extend_from_slice(&(endpoint_count as u16).to_le_bytes());
+    basis_header.extend_from_slice(&(codebooks_offset as u32).to_le_bytes());
+    basis_header.extend_from_slice(&(endpoints_length as u32).to_le_bytes()[..3]);
+    basis_header.extend_from_slice(&(selector_count as u16).to_le_bytes());
+    basis_header.extend_from_slice(&((codebooks_offset + endpoints_length) as u32).to_le_bytes());
+    basis_header.extend_from_slice(&(selectors_length as u32).to_le_bytes()[..3]);
+    basis_header.extend_from_slice(
+        &((codebooks_offset + endpoints_length + selectors_length) as u32).to_le_bytes(),
+    );
+    basis_header.extend_from_slice(&(tables_length as u32).to_le_bytes());
+    basis_header.extend_from_slice(&(slice_descs_start as u32).to_le_bytes());
+    // No extended data.
+    basis_header.extend_from_slice(&[0; 4 + 4]);
+
+    let header_crc = basis_crc16(&basis_header[8..]);
+    basis_header[6..8].copy_from_slice(&header_crc.to_le_bytes());
+
+    let mut file = basis_header;
+    file.extend_from_slice(&data);
+
+    BASIS_TRANSCODER_INIT.call_once(basis_universal::transcoder_init);
+
+    let mut transcoder = basis_universal::Transcoder::new();
+
+    transcoder
+        .prepare_transcoding(&file)
+        .map_err(|_| anyhow::anyhow!("Failed to prepare ETC1S data for transcoding"))?;
+
+    (0..levels.len())
+        .map(|level| {
+            let level_images = (0..images)
+                .map(|image| {
+                    transcoder
+                        .transcode_image_level(
+                            &file,
+                            target.texture_format,
+                            basis_universal::TranscodeParameters {
+                                image_index: image as u32,
+                                level_index: level as u32,
+                                decode_flags: None,
+                                output_row_pitch_in_blocks_or_pixels: None,
+                                output_rows_in_pixels: None,
+                            },
+                        )
+                        .map_err(|error| {
+                            anyhow::anyhow!("Failed to transcode ETC1S image: {:?}", error)
+                        })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+
+            Ok(level_images.concat())
+        })
+        .collect()
+}
+
+// The number of layers in a 2D texture, with each face of a cubemap counted as a layer.
+fn array_layer_count(header: &ktx2::Header) -> u32 {
+    header.layer_count.max(1) * header.face_count.max(1)
+}
+
+// Apply a function to every 2D image (array layer, cubemap face or depth slice) of every mip level.
+// Images are made up of blocks of `block_size` bytes, each covering `block_dimensions` pixels.
+fn map_images(
+    levels: &[Vec<u8>],
+    header: &ktx2::Header,
+    (block_width, block_height): (u32, u32),
+    block_size: u32,
+    function: impl Fn(&[u8], u32, u32) -> anyhow::Result<Vec<u8>>,
+) -> anyhow::Result<Vec<Vec<u8>>> {
+    levels
+        .iter()
+        .enumerate()
+        .map(|(i, level)| {
+            let width = (header.pixel_width >> i).max(1);
+            let height = (header.pixel_height >> i).max(1);
+            let depth = (header.pixel_depth >> i).max(1);
+            let images_per_level = (array_layer_count(header) * depth) as usize;
+
+            let image_length =
+                (width.div_ceil(block_width) * height.div_ceil(block_height) * block_size) as usize;
+
+            if level.len() != image_length * images_per_level {
+                return Err(anyhow::anyhow!(
+                    "Level {} is {} bytes long instead of {}",
+                    i,
+                    level.len(),
+                    image_length * images_per_level
+                ));
+            }
+
+            let images = level
+                .chunks(image_length)
+                .map(|image| function(image, width, height))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+
+            Ok(images.concat())
+        })
+        .collect()
+}
+
+fn decompress_level(
+    level: &ktx2::Level,
+    scheme: Option<ktx2::SupercompressionScheme>,
+) -> anyhow::Result<Vec<u8>> {
+    let length = level.uncompressed_byte_length as usize;
+
+    match scheme {
+        None => Ok(level.data.to_vec()),
+        Some(ktx2::SupercompressionScheme::Zstandard) => zstd::bulk::decompress(level.data, length)
+            .map_err(|error| anyhow::anyhow!("Failed to decompress Zstandard level: {}", error)),
+        Some(ktx2::SupercompressionScheme::ZLIB) => {
+            let mut bytes = Vec::with_capacity(length);
+
+            std::io::Read::read_to_end(&mut flate2::read::ZlibDecoder::new(level.data), &mut bytes)
+                .map_err(|error| anyhow::anyhow!("Failed to decompress ZLIB level: {}", error))?;
+
+            Ok(bytes)
+        }
+        // BasisLZ levels are decoded along with the supercompression global data when they're
+        // transcoded.
+        Some(ktx2::SupercompressionScheme::BasisLZ) => Ok(level.data.to_vec()),
+        Some(other) => Err(anyhow::anyhow!(
+            "Unsupported supercompression scheme: {:?}",
+            other
+        )),
+    }
+}
+
+// Read a KTX2 file into a texture descriptor and the data to upload, decompressing or transcoding
+// it into a format supported by `features`.
+fn read_ktx2(
+    bytes: &[u8],
+    features: wgpu::Features,
+) -> anyhow::Result<(
+    wgpu::TextureDescriptor<'static>,
+    wgpu::TextureViewDimension,
+    Vec<u8>,
+)> {
+    let reader = ktx2::Reader::new(bytes)
+        .map_err(|error| anyhow::anyhow!("Failed to parse KTX2 file: {:?}", error))?;
+
+    let header = reader.header();
+
+    let levels = reader
+        .levels()
+        .map(|level| decompress_level(&level, header.supercompression_scheme))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let (format, levels) = match header.format {
+        Some(format) => {
+            let format = wgpu_format(format)?;
+
+            if features.contains(format.required_features()) {
+                (format, levels)
             } else {
-                wgpu::TextureDimension::D2
-            },
-            format: match header.format.unwrap() {
-                ktx2::Format::E5B9G9R9_UFLOAT_PACK32 => wgpu::TextureFormat::Rgb9e5Ufloat,
-                ktx2::Format::R8G8B8A8_SRGB => wgpu::TextureFormat::Rgba8UnormSrgb,
-                ktx2::Format::R8G8B8A8_UNORM => wgpu::TextureFormat::Rgba8Unorm,
-                other => panic!("{:?}", other),
+                log::warn!(
+                    "{:?} textures are not supported by the device. Decompressing on the CPU.",
+                    format
+                );
+
+                let decompressed_format = if format.is_srgb() {
+                    wgpu::TextureFormat::Rgba8UnormSrgb
+                } else {
+                    wgpu::TextureFormat::Rgba8Unorm
+                };
+
+                let block_size = format.block_size(None).ok_or_else(|| {
+                    anyhow::anyhow!("{:?} textures can't be decompressed", format)
+                })?;
+
+                let levels = map_images(
+                    &levels,
+                    &header,
+                    format.block_dimensions(),
+                    block_size,
+                    |image, width, height| decompress_image(image, format, width, height),
+                )?;
+
+                (decompressed_format, levels)
+            }
+        }
+        // Basis Universal files have an undefined format, with the actual encoding specified in
+        // the data format descriptor.
+        None => {
+            let block = reader
+                .dfd_blocks()
+                .find(|block| block.header == ktx2::DfdHeader::BASIC)
+                .ok_or_else(|| anyhow::anyhow!("KTX2 file has no basic data format descriptor"))?;
+
+            let descriptor = ktx2::DfdBlockBasic::parse(block.data).map_err(|error| {
+                anyhow::anyhow!("Failed to parse data format descriptor: {:?}", error)
+            })?;
+
+            let srgb = descriptor.header.transfer_function == Some(ktx2::TransferFunction::SRGB);
+            let target = basis_target(features, srgb);
+
+            let levels = match descriptor.header.color_model {
+                Some(ktx2::ColorModel::UASTC) => {
+                    // UASTC blocks are always 4x4 pixels in 16 bytes.
+                    map_images(&levels, &header, (4, 4), 16, |image, width, height| {
+                        transcode_uastc_image(image, width, height, target.block_format)
+                    })?
+                }
+                Some(ktx2::ColorModel::ETC1S)
+                    if header.supercompression_scheme
+                        == Some(ktx2::SupercompressionScheme::BasisLZ) =>
+                {
+                    transcode_etc1s(
+                        &levels,
+                        reader.supercompression_global_data(),
+                        &header,
+                        &target,
+                        srgb,
+                    )?
+                }
+                other => {
+                    return Err(anyhow::anyhow!(
+                        "Unsupported KTX2 color model: {:?} with supercompression: {:?}",
+                        other,
+                        header.supercompression_scheme
+                    ))
+                }
+            };
+
+            (target.format, levels)
+        }
+    };
+
+    let is_cubemap = header.face_count == 6;
+    let is_array = header.layer_count > 0;
+
+    let (dimension, view_dimension, depth_or_array_layers) = if header.pixel_depth > 0 {
+        (
+            wgpu::TextureDimension::D3,
+            wgpu::TextureViewDimension::D3,
+            header.pixel_depth,
+        )
+    } else if header.pixel_height == 0 {
+        (
+            wgpu::TextureDimension::D1,
+            wgpu::TextureViewDimension::D1,
+            1,
+        )
+    } else {
+        (
+            wgpu::TextureDimension::D2,
+            match (is_cubemap, is_array) {
+                (true, true) => wgpu::TextureViewDimension::CubeArray,
+                (true, false) => wgpu::TextureViewDimension::Cube,
+                (false, true) => wgpu::TextureViewDimension::D2Array,
+                (false, false) => wgpu::TextureViewDimension::D2,
             },
-            usage: wgpu::TextureUsages::TEXTURE_BINDING,
-            view_formats: &[],
+            array_layer_count(&header),
+        )
+    };
+
+    // Swizzle bytes from being like (L = layer, M = mip) L0M0 L1M0.. to being L0M0 L0M1... The
+    // depth slices of 3D textures stay together.
+    let layers = match dimension {
+        wgpu::TextureDimension::D3 => 1,
+        _ => depth_or_array_layers as usize,
+    };
+    let mut bytes = Vec::with_capacity(levels.iter().map(|level| level.len()).sum());
+
+    for i in 0..layers {
+        for level in &levels {
+            let layer_length = level.len() / layers;
+
+            bytes.extend_from_slice(&level[i * layer_length..(i + 1) * layer_length]);
+        }
+    }
+
+    let descriptor = wgpu::TextureDescriptor {
+        label: None,
+        size: wgpu::Extent3d {
+            width: header.pixel_width,
+            height: header.pixel_height.max(1),
+            depth_or_array_layers,
         },
-        &bytes,
-    )
+        mip_level_count: header.level_count.max(1),
+        sample_count: 1,
+        dimension,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    };
+
+    Ok((descriptor, view_dimension, bytes))
+}
+
+// A loaded texture along with the dimension that views of it should have by default.
+pub struct LoadedTexture {
+    pub texture: wgpu::Texture,
+    pub view_dimension: wgpu::TextureViewDimension,
+}
+
+impl LoadedTexture {
+    pub fn create_view(&self) -> wgpu::TextureView {
+        self.texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(self.view_dimension),
+            ..Default::default()
+        })
+    }
+}
+
+// Load a KTX2 file, with `extra_usages` added to the usual `TEXTURE_BINDING`. Files that only
+// contain a base level get a generated mip chain if a mip generator is given and supports their
+// format.
+//
+// Block-compressed formats are decompressed on the CPU if the device doesn't support them, and
+// Basis Universal files are transcoded to whatever the device does support.
+pub fn load_ktx2(
+    bytes: &[u8],
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    extra_usages: wgpu::TextureUsages,
+    mip_generator: Option<&MipGenerator>,
+) -> anyhow::Result<LoadedTexture> {
+    let (mut descriptor, view_dimension, bytes) = read_ktx2(bytes, device.features())?;
+
+    descriptor.usage |= extra_usages;
+
+    let texture = match mip_generator {
+        Some(mip_generator)
+            if descriptor.mip_level_count == 1
+                && descriptor.dimension == wgpu::TextureDimension::D2
+                && mip_generator.supports(descriptor.format) =>
+        {
+            mip_generator.create_texture_with_mips(device, queue, &descriptor, &bytes)
+        }
+        _ => device.create_texture_with_data(queue, &descriptor, &bytes),
+    };
+
+    Ok(LoadedTexture {
+        texture,
+        view_dimension,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Build a square KTX2 file with the levels stored one after the other.
+    fn ktx2_file(
+        format: ktx2::Format,
+        size: u32,
+        depth: u32,
+        layer_count: u32,
+        face_count: u32,
+        scheme: Option<ktx2::SupercompressionScheme>,
+        levels: &[(Vec<u8>, usize)],
+    ) -> Vec<u8> {
+        ktx2_file_with_descriptor(
+            format.value(),
+            size,
+            depth,
+            layer_count,
+            face_count,
+            scheme,
+            &[],
+            &[],
+            levels,
+        )
+    }
+
+    // Like `ktx2_file`, but with a raw format value, data format descriptor blocks and
+    // supercompression global data.
+    #[allow(clippy::too_many_arguments)]
+    fn ktx2_file_with_descriptor(
+        format: u32,
+        size: u32,
+        depth: u32,
+        layer_count: u32,
+        face_count: u32,
+        scheme: Option<ktx2::SupercompressionScheme>,
+        dfd_blocks: &[u8],
+        global_data: &[u8],
+        levels: &[(Vec<u8>, usize)],
+    ) -> Vec<u8> {
+        let header_length = 80;
+        let level_index_length = levels.len() * 24;
+        let dfd_offset = header_length + level_index_length;
+        // The data format descriptor starts with its total size.
+        let dfd_length = 4 + dfd_blocks.len();
+        let global_data_offset = dfd_offset + dfd_length;
+        let mut level_offset = global_data_offset + global_data.len();
+
+        let mut file = Vec::new();
+        file.extend_from_slice(&KTX2_IDENTIFIER);
+
+        for value in [
+            format,
+            1,
+            size,
+            size,
+            depth,
+            layer_count,
+            face_count,
+            levels.len() as u32,
+            scheme.map(|scheme| scheme.value()).unwrap_or(0),
+            dfd_offset as u32,
+            dfd_length as u32,
+            0,
+            0,
+        ] {
+            file.extend_from_slice(&value.to_le_bytes());
+        }
+
+        for value in [global_data_offset, global_data.len()] {
+            file.extend_from_slice(&(value as u64).to_le_bytes());
+        }
+
+        for (data, uncompressed_length) in levels {
+            for value in [level_offset, data.len(), *uncompressed_length] {
+                file.extend_from_slice(&(value as u64).to_le_bytes());
+            }
+
+            level_offset += data.len();
+        }
+
+        file.extend_from_slice(&(dfd_length as u32).to_le_bytes());
+        file.extend_from_slice(dfd_blocks);
+        file.extend_from_slice(global_data);
+
+        for (data, _) in levels {
+            file.extend_from_slice(data);
+        }
+
+        file
+    }
+
+    // A level where every byte of a face is `face + level * 16`, so faces and levels can be told
+    // apart after swizzling.
+    fn level(level: usize, size: u32, faces: usize) -> Vec<u8> {
+        let face_length = (size as usize >> level).pow(2) * 4;
+
+        (0..faces)
+            .flat_map(|face| vec![(face + level * 16) as u8; face_length])
+            .collect()
+    }
+
+    fn uncompressed(level: Vec<u8>) -> (Vec<u8>, usize) {
+        let length = level.len();
+        (level, length)
+    }
+
+    #[test]
+    fn uncompressed_levels() {
+        let levels = [level(0, 4, 1), level(1, 4, 1), level(2, 4, 1)];
+
+        let file = ktx2_file(
+            ktx2::Format::R8G8B8A8_UNORM,
+            4,
+            0,
+            0,
+            1,
+            None,
+            &levels.clone().map(uncompressed),
+        );
+
+        let (descriptor, _, bytes) = read_ktx2(&file, wgpu::Features::empty()).unwrap();
+
+        assert_eq!(descriptor.format, wgpu::TextureFormat::Rgba8Unorm);
+        assert_eq!(descriptor.mip_level_count, 3);
+        assert_eq!(bytes, levels.concat());
+    }
+
+    #[test]
+    fn cubemap_faces_are_swizzled() {
+        let levels = [level(0, 2, 6), level(1, 2, 6)];
+
+        let file = ktx2_file(
+            ktx2::Format::R8G8B8A8_SRGB,
+            2,
+            0,
+            0,
+            6,
+            None,
+            &levels.clone().map(uncompressed),
+        );
+
+        let (descriptor, _, bytes) = read_ktx2(&file, wgpu::Features::empty()).unwrap();
+
+        assert_eq!(descriptor.size.depth_or_array_layers, 6);
+
+        // Each face has a 2x2 base level followed by a 1x1 level.
+        let expected: Vec<u8> = (0..6)
+            .flat_map(|face| [vec![face; 16], vec![face + 16; 4]].concat())
+            .collect();
+
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn view_dimensions() {
+        let read = |depth, layer_count, face_count, levels: &[Vec<u8>]| {
+            let levels: Vec<_> = levels.iter().cloned().map(uncompressed).collect();
+
+            let file = ktx2_file(
+                ktx2::Format::R8G8B8A8_UNORM,
+                2,
+                depth,
+                layer_count,
+                face_count,
+                None,
+                &levels,
+            );
+
+            read_ktx2(&file, wgpu::Features::empty()).unwrap()
+        };
+
+        let (descriptor, view_dimension, _) = read(0, 3, 1, &[level(0, 2, 3)]);
+        assert_eq!(view_dimension, wgpu::TextureViewDimension::D2Array);
+        assert_eq!(descriptor.size.depth_or_array_layers, 3);
+
+        let (descriptor, view_dimension, _) = read(0, 2, 6, &[level(0, 2, 12)]);
+        assert_eq!(view_dimension, wgpu::TextureViewDimension::CubeArray);
+        assert_eq!(descriptor.size.depth_or_array_layers, 12);
+
+        // The depth slices of each level of a 3D texture stay together.
+        let levels = [level(0, 2, 2), level(1, 2, 1)];
+        let (descriptor, view_dimension, bytes) = read(2, 0, 1, &levels);
+        assert_eq!(view_dimension, wgpu::TextureViewDimension::D3);
+        assert_eq!(descriptor.dimension, wgpu::TextureDimension::D3);
+        assert_eq!(descriptor.size.depth_or_array_layers, 2);
+        assert_eq!(bytes, levels.concat());
+    }
+
+    #[test]
+    fn zstd_and_zlib_levels() {
+        let levels = [level(0, 4, 6), level(1, 4, 6), level(2, 4, 6)];
+
+        let zstd_levels = levels
+            .clone()
+            .map(|level| (zstd::bulk::compress(&level, 0).unwrap(), level.len()));
+
+        let zlib_levels = levels.clone().map(|level| {
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+            std::io::Write::write_all(&mut encoder, &level).unwrap();
+            (encoder.finish().unwrap(), level.len())
+        });
+
+        let uncompressed_file = ktx2_file(
+            ktx2::Format::R8G8B8A8_UNORM,
+            4,
+            0,
+            0,
+            6,
+            None,
+            &levels.map(uncompressed),
+        );
+
+        let (_, _, expected) = read_ktx2(&uncompressed_file, wgpu::Features::empty()).unwrap();
+
+        for (scheme, levels) in [
+            (ktx2::SupercompressionScheme::Zstandard, zstd_levels),
+            (ktx2::SupercompressionScheme::ZLIB, zlib_levels),
+        ] {
+            let file = ktx2_file(
+                ktx2::Format::R8G8B8A8_UNORM,
+                4,
+                0,
+                0,
+                6,
+                Some(scheme),
+                &levels,
+            );
+
+            let (_, _, bytes) = read_ktx2(&file, wgpu::Features::empty()).unwrap();
+
+            assert_eq!(bytes, expected, "{:?}", scheme);
+        }
+    }
+
+    #[test]
+    fn unsupported_formats_are_errors() {
+        let file = ktx2_file(
+            ktx2::Format::R8_UNORM,
+            4,
+            0,
+            0,
+            1,
+            None,
+            &[uncompressed(vec![0; 16])],
+        );
+
+        assert!(read_ktx2(&file, wgpu::Features::empty()).is_err());
+        assert!(read_ktx2(&file[..40], wgpu::Features::empty()).is_err());
+    }
+
+    #[test]
+    fn truncated_levels_are_errors() {
+        // A 4x4 BC1 level is a single 8 byte block.
+        for length in [0, 4, 12] {
+            let file = ktx2_file(
+                ktx2::Format::BC1_RGBA_UNORM_BLOCK,
+                4,
+                0,
+                0,
+                1,
+                None,
+                &[uncompressed(vec![0; length])],
+            );
+
+            assert!(read_ktx2(&file, wgpu::Features::empty()).is_err());
+        }
+    }
+
+    // Encode a `.basis` file and split it up into a KTX2 file the way `toktx` does, so that
+    // `transcode_etc1s` has to put it back together.
+    #[test]
+    fn basis_lz_levels() {
+        let size = 8;
+
+        let pixels: Vec<u8> = (0..size * size)
+            .flat_map(|i| [(i * 4) as u8, (255 - i * 4) as u8, (i % 8 * 32) as u8, 255])
+            .collect();
+
+        let mut params = basis_universal::CompressorParams::new();
+        params.set_basis_format(basis_universal::BasisTextureFormat::ETC1S);
+        params.set_color_space(basis_universal::ColorSpace::Linear);
+        params.set_generate_mipmaps(true);
+        params.source_image_mut(0).init(&pixels, size, size, 4);
+
+        let mut compressor = basis_universal::Compressor::new(1);
+
+        unsafe {
+            assert!(compressor.init(&params));
+            compressor.process().unwrap();
+        }
+
+        let basis_file = compressor.basis_file();
+
+        let read_uint = |offset: usize, length: usize| {
+            let mut bytes = [0; 4];
+            bytes[..length].copy_from_slice(&basis_file[offset..offset + length]);
+            u32::from_le_bytes(bytes) as usize
+        };
+
+        let slice_count = read_uint(14, 3);
+        let endpoint_count = read_uint(39, 2);
+        let endpoints = read_uint(41, 4)..read_uint(41, 4) + read_uint(45, 3);
+        let selector_count = read_uint(48, 2);
+        let selectors = read_uint(50, 4)..read_uint(50, 4) + read_uint(54, 3);
+        let tables = read_uint(57, 4)..read_uint(57, 4) + read_uint(61, 4);
+        let slice_descs_start = read_uint(65, 4);
+
+        // The image is opaque, so each level has a single RGB slice.
+        let slices: Vec<_> = (0..slice_count)
+            .map(|slice| {
+                let desc = slice_descs_start + slice * 23;
+                let offset = read_uint(desc + 13, 4);
+                basis_file[offset..offset + read_uint(desc + 17, 4)].to_vec()
+            })
+            .collect();
+
+        let mut global_data = Vec::new();
+
+        for value in [
+            (endpoint_count | selector_count << 16) as u32,
+            endpoints.len() as u32,
+            selectors.len() as u32,
+            tables.len() as u32,
+            0,
+        ] {
+            global_data.extend_from_slice(&value.to_le_bytes());
+        }
+
+        for slice in &slices {
+            for value in [0, 0, slice.len() as u32, 0, 0] {
+                global_data.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        global_data.extend_from_slice(&basis_file[endpoints]);
+        global_data.extend_from_slice(&basis_file[selectors]);
+        global_data.extend_from_slice(&basis_file[tables]);
+
+        // A basic descriptor block for ETC1S with a linear transfer function and one sample.
+        let mut dfd_block = Vec::new();
+        dfd_block.extend_from_slice(&0_u32.to_le_bytes());
+        dfd_block.extend_from_slice(&2_u16.to_le_bytes());
+        dfd_block.extend_from_slice(&40_u16.to_le_bytes());
+        dfd_block.extend_from_slice(&[
+            ktx2::ColorModel::ETC1S.value(),
+            ktx2::ColorPrimaries::BT709.value(),
+            ktx2::TransferFunction::Linear.value(),
+            0,
+        ]);
+        dfd_block.extend_from_slice(&[3, 3, 0, 0]);
+        dfd_block.extend_from_slice(&[0; 8]);
+        dfd_block.extend_from_slice(&(63_u32 << 16).to_le_bytes());
+        dfd_block.extend_from_slice(&[0; 12]);
+
+        let levels: Vec<_> = slices
+            .iter()
+            .map(|slice| (slice.clone(), slice.len()))
+            .collect();
+
+        let file = ktx2_file_with_descriptor(
+            0,
+            size,
+            0,
+            0,
+            1,
+            Some(ktx2::SupercompressionScheme::BasisLZ),
+            &dfd_block,
+            &global_data,
+            &levels,
+        );
+
+        let (descriptor, _, bytes) = read_ktx2(&file, wgpu::Features::empty()).unwrap();
+
+        assert_eq!(descriptor.format, wgpu::TextureFormat::Rgba8Unorm);
+        assert_eq!(descriptor.mip_level_count, levels.len() as u32);
+
+        let mut transcoder = basis_universal::Transcoder::new();
+        transcoder.prepare_transcoding(basis_file).unwrap();
+
+        let expected: Vec<u8> = (0..levels.len() as u32)
+            .flat_map(|level| {
+                transcoder
+                    .transcode_image_level(
+                        basis_file,
+                        basis_universal::TranscoderTextureFormat::RGBA32,
+                        basis_universal::TranscodeParameters {
+                            image_index: 0,
+                            level_index: level,
+                            decode_flags: None,
+                            output_row_pitch_in_blocks_or_pixels: None,
+                            output_rows_in_pixels: None,
+                        },
+                    )
+                    .unwrap()
+            })
+            .collect();
+
+        assert_eq!(bytes, expected);
+    }
 }
@@ -0,0 +1,170 @@
+use crate::bindless_textures::{BindlessTextures, TextureHandle};
+use crate::mip_generation::MipGenerator;
+use crate::texture_loading::{load_image, ImageFormat};
+use parking_lot::{Condvar, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use wgpu::util::DeviceExt;
+
+// What a texture slot shows while its image is still loading.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Placeholder {
+    White,
+    Black,
+    // A tangent-space normal pointing straight out of the surface.
+    FlatNormal,
+}
+
+struct LoadedImage {
+    slot: TextureHandle,
+    name: String,
+    result: anyhow::Result<wgpu::TextureView>,
+}
+
+#[derive(Default)]
+struct Shared {
+    loaded: Mutex<Vec<LoadedImage>>,
+    // Signalled whenever an image finishes loading.
+    condvar: Condvar,
+    // The number of images that haven't finished loading yet. Only decremented with `loaded`
+    // locked so that `wait_for_all` can't miss a wakeup.
+    pending: AtomicUsize,
+}
+
+// Decodes and uploads images on the rayon thread pool so that models can be drawn with
+// placeholder textures in the meantime.
+pub struct TextureStreamer {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    mip_generator: Option<Arc<MipGenerator>>,
+    white: wgpu::Texture,
+    black: wgpu::Texture,
+    flat_normal: wgpu::Texture,
+    shared: Arc<Shared>,
+}
+
+fn create_placeholder_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    pixel: [u8; 4],
+    label: &str,
+) -> wgpu::Texture {
+    device.create_texture_with_data(
+        queue,
+        &wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        },
+        &pixel,
+    )
+}
+
+impl TextureStreamer {
+    pub fn new(
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        mip_generator: Option<Arc<MipGenerator>>,
+    ) -> Self {
+        Self {
+            white: create_placeholder_texture(&device, &queue, [255; 4], "white placeholder"),
+            black: create_placeholder_texture(&device, &queue, [0, 0, 0, 255], "black placeholder"),
+            flat_normal: create_placeholder_texture(
+                &device,
+                &queue,
+                [128, 128, 255, 255],
+                "flat normal placeholder",
+            ),
+            device,
+            queue,
+            mip_generator,
+            shared: Default::default(),
+        }
+    }
+
+    pub fn placeholder_view(&self, placeholder: Placeholder) -> wgpu::TextureView {
+        let texture = match placeholder {
+            Placeholder::White => &self.white,
+            Placeholder::Black => &self.black,
+            Placeholder::FlatNormal => &self.flat_normal,
+        };
+
+        texture.create_view(&Default::default())
+    }
+
+    // Start loading an image in the background. It'll replace the view in `slot` once it's been
+    // uploaded and `update` is called, unless the slot has been removed by then.
+    pub fn load(
+        &self,
+        slot: TextureHandle,
+        name: String,
+        bytes: Vec<u8>,
+        format: ImageFormat,
+        srgb: bool,
+    ) {
+        self.shared.pending.fetch_add(1, Ordering::SeqCst);
+
+        let device = self.device.clone();
+        let queue = self.queue.clone();
+        let mip_generator = self.mip_generator.clone();
+        let shared = self.shared.clone();
+
+        rayon::spawn(move || {
+            let result = load_image(
+                &bytes,
+                format,
+                srgb,
+                &device,
+                &queue,
+                mip_generator.as_deref(),
+            )
+            .map(|texture| texture.create_view(&Default::default()));
+
+            let mut loaded = shared.loaded.lock();
+            loaded.push(LoadedImage { slot, name, result });
+            shared.pending.fetch_sub(1, Ordering::SeqCst);
+            shared.condvar.notify_all();
+        });
+    }
+
+    // Swap any images that have finished loading into their slots. Returns whether any slots
+    // were changed.
+    pub fn update(&self, bindless_textures: &mut BindlessTextures) -> bool {
+        let loaded = std::mem::take(&mut *self.shared.loaded.lock());
+        let changed = !loaded.is_empty();
+
+        for LoadedImage { slot, name, result } in loaded {
+            match result {
+                Ok(view) => {
+                    bindless_textures.replace(slot, view);
+                }
+                // The placeholder is left in place.
+                Err(error) => log::error!("Failed to load {}: {}", name, error),
+            }
+        }
+
+        changed
+    }
+
+    // Block until every image has finished loading and been swapped into its slot.
+    pub fn wait_for_all(&self, bindless_textures: &mut BindlessTextures) {
+        {
+            let mut loaded = self.shared.loaded.lock();
+
+            while self.shared.pending.load(Ordering::SeqCst) > 0 {
+                self.shared.condvar.wait(&mut loaded);
+            }
+        }
+
+        self.update(bindless_textures);
+    }
+}